@@ -0,0 +1,178 @@
+//! `allyabase://service/path` URLs, which name an allyabase service instead
+//! of a host, for deployments where every service runs in one container.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use crate::BdoError;
+
+pub const SCHEME: &str = "allyabase://";
+
+/// The ports allyabase services listen on inside a container, as the BDO
+/// server translates them.
+pub const SERVICE_PORTS: &[(&str, u16)] = &[
+    ("sanora", 7243),
+    ("julia", 3000),
+    ("continuebee", 2999),
+    ("pref", 3002),
+    ("bdo", 3003),
+    ("joan", 3004),
+    ("addie", 3005),
+    ("fount", 3006),
+    ("dolores", 3007),
+    ("minnie", 2525),
+    ("aretha", 7277),
+    ("covenant", 3011),
+];
+
+/// Where each service can be reached. Defaults to `SERVICE_PORTS` on `127.0.0.1`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServiceTable {
+    host: String,
+    ports: HashMap<String, u16>,
+}
+
+impl Default for ServiceTable {
+    fn default() -> Self {
+        ServiceTable {
+            host: "127.0.0.1".to_string(),
+            ports: SERVICE_PORTS.iter().map(|(service, port)| (service.to_string(), *port)).collect(),
+        }
+    }
+}
+
+impl ServiceTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The host translated URLs point at.
+    pub fn with_host(mut self, host: &str) -> Self {
+        self.host = host.to_string();
+        self
+    }
+
+    /// Adds a service, or moves one to another port.
+    pub fn with_port(mut self, service: &str, port: u16) -> Self {
+        self.ports.insert(service.to_string(), port);
+        self
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self, service: &str) -> Option<u16> {
+        self.ports.get(service).copied()
+    }
+}
+
+/// A parsed `allyabase://service/path?query` URL.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AllyabaseUrl {
+    service: String,
+    /// Everything after the service, starting with `/`, `?` or `#`, or empty.
+    rest: String,
+}
+
+fn invalid(url: &str, reason: &str) -> BdoError {
+    BdoError::Config(format!("invalid allyabase URL {}: {}", url, reason))
+}
+
+fn is_service_name(service: &str) -> bool {
+    !service.is_empty()
+        && service.starts_with(|c: char| c.is_ascii_lowercase())
+        && service.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+impl AllyabaseUrl {
+    /// `allyabase://{service}{path}`. A path that doesn't start with `/` gets one.
+    pub fn new(service: &str, path: &str) -> Result<Self, BdoError> {
+        let rest = if path.is_empty() || path.starts_with(['/', '?', '#']) {
+            path.to_string()
+        } else {
+            format!("/{}", path)
+        };
+
+        Self::parse(&format!("{}{}{}", SCHEME, service, rest))
+    }
+
+    pub fn parse(url: &str) -> Result<Self, BdoError> {
+        let after_scheme = url.get(..SCHEME.len())
+            .filter(|scheme| scheme.eq_ignore_ascii_case(SCHEME))
+            .map(|_| &url[SCHEME.len()..])
+            .ok_or_else(|| invalid(url, "it doesn't start with allyabase://"))?;
+
+        let service_end = after_scheme.find(['/', '?', '#']).unwrap_or(after_scheme.len());
+        let service = &after_scheme[..service_end];
+        if !is_service_name(service) {
+            return Err(invalid(url, "the service must be a lowercase name like bdo or sanora"));
+        }
+        let rest = &after_scheme[service_end..];
+        if rest.chars().any(char::is_whitespace) {
+            return Err(invalid(url, "it contains whitespace"));
+        }
+
+        Ok(AllyabaseUrl {
+            service: service.to_string(),
+            rest: rest.to_string(),
+        })
+    }
+
+    /// Whether `url` uses the `allyabase://` scheme at all.
+    pub fn is_allyabase(url: &str) -> bool {
+        url.get(..SCHEME.len()).is_some_and(|scheme| scheme.eq_ignore_ascii_case(SCHEME))
+    }
+
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    /// The path, without the query or fragment.
+    pub fn path(&self) -> &str {
+        let end = self.rest.find(['?', '#']).unwrap_or(self.rest.len());
+        &self.rest[..end]
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        let start = self.rest.find('?')? + 1;
+        let end = self.rest.find('#').filter(|end| *end >= start).unwrap_or(self.rest.len());
+        Some(&self.rest[start..end])
+    }
+
+    /// The URL with the default `ServiceTable`, e.g. `http://127.0.0.1:3003/`.
+    pub fn translate(&self) -> Result<String, BdoError> {
+        self.translate_with(&ServiceTable::default())
+    }
+
+    /// The `http://host:port/path` this URL stands for under `table`.
+    pub fn translate_with(&self, table: &ServiceTable) -> Result<String, BdoError> {
+        let port = table.port(&self.service)
+            .ok_or_else(|| invalid(&self.to_string(), "the service isn't in the service table"))?;
+
+        Ok(format!("http://{}:{}{}", table.host(), port, self.rest))
+    }
+}
+
+/// Translates `url` if it's an `allyabase://` URL and returns anything else as it is.
+pub fn translate_url(url: &str, table: &ServiceTable) -> Result<String, BdoError> {
+    if AllyabaseUrl::is_allyabase(url) {
+        AllyabaseUrl::parse(url)?.translate_with(table)
+    } else {
+        Ok(url.to_string())
+    }
+}
+
+impl FromStr for AllyabaseUrl {
+    type Err = BdoError;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        Self::parse(url)
+    }
+}
+
+impl Display for AllyabaseUrl {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}{}", SCHEME, self.service, self.rest)
+    }
+}
@@ -0,0 +1,95 @@
+//! The BDO operations as a trait, so code that talks to a BDO server can
+//! hold a `Box<dyn BdoApi>` or `Arc<dyn BdoApi>` and be handed a
+//! `mock::MockBdo` in its tests.
+
+use serde_json::Value;
+use std::future::Future;
+use std::pin::Pin;
+use crate::structs::{AddTemplateResponse, BDOUser, EmojicodeResponse, PubKeyEmojicodeResponse, ShortCodeResponse, SuccessResult, TeleportResponse, Template};
+use crate::{BdoError, Bases, Spellbook, BDO};
+
+/// What every `BdoApi` method returns. Boxed so the trait stays object safe.
+pub type BdoFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, BdoError>> + Send + 'a>>;
+
+/// The operations `BDO` offers, minus the builder-style configuration and
+/// the generic typed helpers, which can't be called through a `dyn BdoApi`.
+/// Use `typed::from_bdo` on the results instead.
+pub trait BdoApi: Send + Sync {
+    fn create_user<'a>(&'a self, hash: &'a str, bdo: &'a Value, is_public: &'a bool) -> BdoFuture<'a, BDOUser>;
+    fn update_bdo<'a>(&'a self, uuid: &'a str, hash: &'a str, bdo: &'a Value, is_public: &'a bool) -> BdoFuture<'a, BDOUser>;
+    fn get_bdo<'a>(&'a self, uuid: &'a str, hash: &'a str) -> BdoFuture<'a, BDOUser>;
+    fn get_public_bdo<'a>(&'a self, uuid: &'a str, hash: &'a str, pub_key: &'a str) -> BdoFuture<'a, BDOUser>;
+    fn get_bases<'a>(&'a self, uuid: &'a str, hash: &'a str) -> BdoFuture<'a, Bases>;
+    fn save_bases<'a>(&'a self, uuid: &'a str, hash: &'a str, bases: &'a Bases) -> BdoFuture<'a, Bases>;
+    fn get_spellbooks<'a>(&'a self, uuid: &'a str, hash: &'a str) -> BdoFuture<'a, Vec<Spellbook>>;
+    fn put_spellbook<'a>(&'a self, uuid: &'a str, hash: &'a str, spellbook: &'a Spellbook) -> BdoFuture<'a, Vec<Spellbook>>;
+    fn delete_user<'a>(&'a self, uuid: &'a str, hash: &'a str) -> BdoFuture<'a, SuccessResult>;
+    fn teleport<'a>(&'a self, uuid: &'a str, hash: &'a str, url: &'a str) -> BdoFuture<'a, TeleportResponse>;
+    fn get_bdo_by_emojicode<'a>(&'a self, emojicode: &'a str) -> BdoFuture<'a, EmojicodeResponse>;
+    fn get_bdo_by_short_code<'a>(&'a self, short_code: &'a str) -> BdoFuture<'a, ShortCodeResponse>;
+    fn get_emojicode_for_pub_key<'a>(&'a self, pub_key: &'a str) -> BdoFuture<'a, PubKeyEmojicodeResponse>;
+    fn get_templates<'a>(&'a self, hash: &'a str) -> BdoFuture<'a, Vec<Template>>;
+    fn add_template<'a>(&'a self, hash: &'a str, emojicode: &'a str) -> BdoFuture<'a, AddTemplateResponse>;
+}
+
+impl BdoApi for BDO {
+    fn create_user<'a>(&'a self, hash: &'a str, bdo: &'a Value, is_public: &'a bool) -> BdoFuture<'a, BDOUser> {
+        Box::pin(BDO::create_user(self, hash, bdo, is_public))
+    }
+
+    fn update_bdo<'a>(&'a self, uuid: &'a str, hash: &'a str, bdo: &'a Value, is_public: &'a bool) -> BdoFuture<'a, BDOUser> {
+        Box::pin(BDO::update_bdo(self, uuid, hash, bdo, is_public))
+    }
+
+    fn get_bdo<'a>(&'a self, uuid: &'a str, hash: &'a str) -> BdoFuture<'a, BDOUser> {
+        Box::pin(BDO::get_bdo(self, uuid, hash))
+    }
+
+    fn get_public_bdo<'a>(&'a self, uuid: &'a str, hash: &'a str, pub_key: &'a str) -> BdoFuture<'a, BDOUser> {
+        Box::pin(BDO::get_public_bdo(self, uuid, hash, pub_key))
+    }
+
+    fn get_bases<'a>(&'a self, uuid: &'a str, hash: &'a str) -> BdoFuture<'a, Bases> {
+        Box::pin(BDO::get_bases(self, uuid, hash))
+    }
+
+    fn save_bases<'a>(&'a self, uuid: &'a str, hash: &'a str, bases: &'a Bases) -> BdoFuture<'a, Bases> {
+        Box::pin(BDO::save_bases(self, uuid, hash, bases))
+    }
+
+    fn get_spellbooks<'a>(&'a self, uuid: &'a str, hash: &'a str) -> BdoFuture<'a, Vec<Spellbook>> {
+        Box::pin(BDO::get_spellbooks(self, uuid, hash))
+    }
+
+    fn put_spellbook<'a>(&'a self, uuid: &'a str, hash: &'a str, spellbook: &'a Spellbook) -> BdoFuture<'a, Vec<Spellbook>> {
+        Box::pin(BDO::put_spellbook(self, uuid, hash, spellbook))
+    }
+
+    fn delete_user<'a>(&'a self, uuid: &'a str, hash: &'a str) -> BdoFuture<'a, SuccessResult> {
+        Box::pin(BDO::delete_user(self, uuid, hash))
+    }
+
+    fn teleport<'a>(&'a self, uuid: &'a str, hash: &'a str, url: &'a str) -> BdoFuture<'a, TeleportResponse> {
+        Box::pin(BDO::teleport(self, uuid, hash, url))
+    }
+
+    fn get_bdo_by_emojicode<'a>(&'a self, emojicode: &'a str) -> BdoFuture<'a, EmojicodeResponse> {
+        Box::pin(BDO::get_bdo_by_emojicode(self, emojicode))
+    }
+
+    fn get_bdo_by_short_code<'a>(&'a self, short_code: &'a str) -> BdoFuture<'a, ShortCodeResponse> {
+        Box::pin(BDO::get_bdo_by_short_code(self, short_code))
+    }
+
+    fn get_emojicode_for_pub_key<'a>(&'a self, pub_key: &'a str) -> BdoFuture<'a, PubKeyEmojicodeResponse> {
+        Box::pin(BDO::get_emojicode_for_pub_key(self, pub_key))
+    }
+
+    fn get_templates<'a>(&'a self, hash: &'a str) -> BdoFuture<'a, Vec<Template>> {
+        Box::pin(BDO::get_templates(self, hash))
+    }
+
+    fn add_template<'a>(&'a self, hash: &'a str, emojicode: &'a str) -> BdoFuture<'a, AddTemplateResponse> {
+        Box::pin(BDO::add_template(self, hash, emojicode))
+    }
+}
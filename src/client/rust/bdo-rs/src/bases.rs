@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::Bases;
+
+/// One base a user knows about, e.g. `{"name": "Allyabase", "url": "https://allyabase.com"}`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BaseConfig {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub url: String,
+    /// The allyabase services the base runs, e.g. `bdo` or `fount`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<String>,
+    /// Fields this client doesn't know about, kept so they survive a round trip.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl BaseConfig {
+    pub fn new(name: &str, url: &str) -> Self {
+        BaseConfig {
+            name: name.to_string(),
+            url: url.to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_services(mut self, services: &[&str]) -> Self {
+        self.services = services.iter().map(|service| service.to_string()).collect();
+        self
+    }
+
+    pub fn runs(&self, service: &str) -> bool {
+        self.services.iter().any(|running| running == service)
+    }
+}
+
+impl Bases {
+    pub fn base(&self, id: &str) -> Option<&BaseConfig> {
+        self.bases.get(id)
+    }
+
+    /// Adds a base, or replaces the one with the same id, and returns the one it replaced.
+    pub fn add_base(&mut self, id: &str, base: BaseConfig) -> Option<BaseConfig> {
+        self.bases.insert(id.to_string(), base)
+    }
+
+    /// Removes a base, keeping the others in order. This is local only: the
+    /// server merges saved bases into its own, so `save_bases` won't remove it there.
+    pub fn remove_base(&mut self, id: &str) -> Option<BaseConfig> {
+        self.bases.shift_remove(id)
+    }
+
+    /// The bases that run `service`, e.g. every base with a `bdo`.
+    pub fn running<'a>(&'a self, service: &'a str) -> impl Iterator<Item = (&'a str, &'a BaseConfig)> + 'a {
+        self.bases.iter()
+            .filter(move |(_, base)| base.runs(service))
+            .map(|(id, base)| (id.as_str(), base))
+    }
+}
@@ -0,0 +1,140 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use sessionless::Sessionless;
+use tokio::runtime::{Builder, Runtime};
+use crate::structs::{AddTemplateResponse, BDOUser, EmojicodeResponse, PubKeyEmojicodeResponse, ShortCodeResponse, SpellBases, SpellSpellbooks, SpellUser, SuccessResult, TeleportResponse, Template};
+use crate::patch::PatchOperation;
+use crate::teleport::VerifiedTeleport;
+use crate::{BdoConflict, BdoError, Bases, Cached, KeyStore, Spellbook, BDO};
+
+/// Generates a blocking wrapper for each async `BDO` method.
+macro_rules! blocking {
+    ($( fn $name:ident(&self $(, $arg:ident: $ty:ty)*) -> $ret:ty; )*) => {
+        $(
+            pub fn $name(&self $(, $arg: $ty)*) -> Result<$ret, BdoError> {
+                self.runtime.block_on(self.bdo.$name($($arg),*))
+            }
+        )*
+    };
+}
+
+/// A synchronous `BDO` client for CLIs and build scripts.
+///
+/// Every call drives the async client on a private current-thread runtime,
+/// so don't use it from inside another async runtime. That runtime only
+/// runs between calls, so a cache's background refreshes happen during
+/// later calls rather than on their own.
+pub struct BlockingBDO {
+    bdo: BDO,
+    runtime: Runtime,
+}
+
+impl BlockingBDO {
+    pub fn new(base_url: Option<String>, sessionless: Option<Sessionless>) -> Self {
+        Self::from_async(BDO::new(base_url, sessionless))
+    }
+
+    /// Wraps an already configured async client.
+    pub fn from_async(bdo: BDO) -> Self {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to build the blocking BDO runtime");
+
+        BlockingBDO { bdo, runtime }
+    }
+
+    /// The async client underneath, e.g. for its `sessionless` keys.
+    pub fn bdo(&self) -> &BDO {
+        &self.bdo
+    }
+
+    pub fn into_async(self) -> BDO {
+        self.bdo
+    }
+
+    pub fn check_for_user(&mut self, store: &dyn KeyStore, hash: &str) -> Result<Option<String>, BdoError> {
+        self.bdo.check_for_user(store, hash)
+    }
+
+    pub fn ensure_user(&mut self, store: &dyn KeyStore, hash: &str, bdo: &Value, is_public: &bool) -> Result<String, BdoError> {
+        self.runtime.block_on(self.bdo.ensure_user(store, hash, bdo, is_public))
+    }
+
+    pub fn create_user_typed<T: Serialize + DeserializeOwned>(&self, hash: &str, bdo: &T, is_public: &bool) -> Result<BDOUser<T>, BdoError> {
+        self.runtime.block_on(self.bdo.create_user_typed(hash, bdo, is_public))
+    }
+
+    pub fn update_bdo_typed<T: Serialize + DeserializeOwned>(&self, uuid: &str, hash: &str, bdo: &T, is_public: &bool) -> Result<BDOUser<T>, BdoError> {
+        self.runtime.block_on(self.bdo.update_bdo_typed(uuid, hash, bdo, is_public))
+    }
+
+    pub fn get_bdo_as<T: DeserializeOwned>(&self, uuid: &str, hash: &str) -> Result<BDOUser<T>, BdoError> {
+        self.runtime.block_on(self.bdo.get_bdo_as(uuid, hash))
+    }
+
+    pub fn get_public_bdo_as<T: DeserializeOwned>(&self, uuid: &str, hash: &str, pub_key: &str) -> Result<BDOUser<T>, BdoError> {
+        self.runtime.block_on(self.bdo.get_public_bdo_as(uuid, hash, pub_key))
+    }
+
+    pub fn get_bdo_by_emojicode_as<T: DeserializeOwned>(&self, emojicode: &str) -> Result<EmojicodeResponse<T>, BdoError> {
+        self.runtime.block_on(self.bdo.get_bdo_by_emojicode_as(emojicode))
+    }
+
+    pub fn get_bdo_by_short_code_as<T: DeserializeOwned>(&self, short_code: &str) -> Result<ShortCodeResponse<T>, BdoError> {
+        self.runtime.block_on(self.bdo.get_bdo_by_short_code_as(short_code))
+    }
+
+    pub fn update_bdo_with<F>(&self, uuid: &str, hash: &str, is_public: &bool, update: F) -> Result<BDOUser, BdoError>
+    where
+        F: FnMut(&Value) -> Result<Value, BdoError>,
+    {
+        self.runtime.block_on(self.bdo.update_bdo_with(uuid, hash, is_public, update))
+    }
+
+    pub fn update_bdo_with_merge<F, M>(&self, uuid: &str, hash: &str, is_public: &bool, update: F, merge: M) -> Result<BDOUser, BdoError>
+    where
+        F: FnMut(&Value) -> Result<Value, BdoError>,
+        M: FnMut(&BdoConflict) -> Result<Value, BdoError>,
+    {
+        self.runtime.block_on(self.bdo.update_bdo_with_merge(uuid, hash, is_public, update, merge))
+    }
+
+    blocking! {
+        fn create_user(&self, hash: &str, bdo: &Value, is_public: &bool) -> BDOUser;
+        fn update_bdo(&self, uuid: &str, hash: &str, bdo: &Value, is_public: &bool) -> BDOUser;
+        fn get_bdo(&self, uuid: &str, hash: &str) -> BDOUser;
+        fn get_public_bdo(&self, uuid: &str, hash: &str, pub_key: &str) -> BDOUser;
+        fn get_bdo_cached(&self, uuid: &str, hash: &str) -> Cached<BDOUser>;
+        fn get_public_bdo_cached(&self, uuid: &str, hash: &str, pub_key: &str) -> Cached<BDOUser>;
+        fn get_bases_cached(&self, uuid: &str, hash: &str) -> Cached<Bases>;
+        fn get_spellbooks_cached(&self, uuid: &str, hash: &str) -> Cached<Vec<Spellbook>>;
+        fn get_bdo_versioned(&self, uuid: &str, hash: &str) -> (Value, String);
+        fn patch_bdo(&self, uuid: &str, hash: &str, patch: &[PatchOperation], is_public: &bool) -> BDOUser;
+        fn merge_patch_bdo(&self, uuid: &str, hash: &str, patch: &Value, is_public: &bool) -> BDOUser;
+        fn compare_and_swap(&self, uuid: &str, hash: &str, base: &Value, bdo: &Value, is_public: &bool) -> BDOUser;
+        fn get_bases(&self, uuid: &str, hash: &str) -> Bases;
+        fn save_bases(&self, uuid: &str, hash: &str, bases: &Bases) -> Bases;
+        fn get_spellbooks(&self, uuid: &str, hash: &str) -> Vec<Spellbook>;
+        fn put_spellbook(&self, uuid: &str, hash: &str, spellbook: &Spellbook) -> Vec<Spellbook>;
+        fn delete_user(&self, uuid: &str, hash: &str) -> SuccessResult;
+        fn teleport(&self, uuid: &str, hash: &str, url: &str) -> TeleportResponse;
+        fn teleport_verified(&self, uuid: &str, hash: &str, url: &str) -> VerifiedTeleport;
+        fn get_bdo_by_emojicode(&self, emojicode: &str) -> EmojicodeResponse;
+        fn get_bdo_by_short_code(&self, short_code: &str) -> ShortCodeResponse;
+        fn get_emojicode_for_pub_key(&self, pub_key: &str) -> PubKeyEmojicodeResponse;
+        fn get_templates(&self, hash: &str) -> Vec<Template>;
+        fn add_template(&self, hash: &str, emojicode: &str) -> AddTemplateResponse;
+        fn cast_user_create(&self, hash: &str, bdo: &Value, is_public: &bool) -> SpellUser;
+        fn cast_user_bdo(&self, uuid: &str, hash: &str, bdo: &Value, is_public: &bool) -> SpellUser;
+        fn cast_user_bases(&self, uuid: &str, hash: &str, bases: &Bases) -> SpellBases;
+        fn cast_user_spellbooks(&self, uuid: &str, hash: &str, spellbook: &Spellbook) -> SpellSpellbooks;
+    }
+}
+
+impl From<BDO> for BlockingBDO {
+    fn from(bdo: BDO) -> Self {
+        Self::from_async(bdo)
+    }
+}
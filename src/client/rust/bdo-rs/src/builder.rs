@@ -0,0 +1,242 @@
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Url};
+use sessionless::Sessionless;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::allyabase::{translate_url, ServiceTable};
+use crate::cache::{BdoCache, CachePolicy, CacheStore};
+use crate::cas::DEFAULT_CAS_ATTEMPTS;
+use crate::{BdoError, Caster, RetryPolicy, BDO};
+
+pub const DEFAULT_BASE_URL: &str = "https://dev.bdo.allyabase.com/";
+
+/// Configures a `BDO` client beyond what `BDO::new` offers.
+///
+/// ```no_run
+/// use bdo_rs::BDO;
+/// use std::time::Duration;
+///
+/// let bdo = BDO::builder()
+///     .base_url("https://wiki.example.com")
+///     .path_prefix("plugin/allyabase/bdo")
+///     .timeout(Duration::from_secs(10))
+///     .build()
+///     .expect("valid configuration");
+/// ```
+#[derive(Default)]
+pub struct BdoBuilder {
+    base_url: Option<String>,
+    path_prefix: Option<String>,
+    client: Option<Client>,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    user_agent: Option<String>,
+    headers: Vec<(String, String)>,
+    sessionless: Option<Sessionless>,
+    caster: Option<Caster>,
+    resolver_url: Option<String>,
+    retry_policy: Option<RetryPolicy>,
+    service_table: Option<ServiceTable>,
+    signed_public_bdos: bool,
+    teleport_content_hashes: bool,
+    skip_unchanged_writes: bool,
+    cas_attempts: Option<u32>,
+    cache: Option<(Arc<dyn CacheStore>, CachePolicy)>,
+}
+
+impl BdoBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The BDO server, e.g. `https://dev.bdo.allyabase.com` or `allyabase://bdo`.
+    /// A trailing slash is optional.
+    pub fn base_url(mut self, base_url: &str) -> Self {
+        self.base_url = Some(base_url.to_string());
+        self
+    }
+
+    /// A path in front of every route, for BDO servers behind a reverse proxy.
+    pub fn path_prefix(mut self, path_prefix: &str) -> Self {
+        self.path_prefix = Some(path_prefix.to_string());
+        self
+    }
+
+    /// Uses an existing `reqwest::Client` instead of building one.
+    ///
+    /// Timeouts, the user agent and default headers belong to the client, so
+    /// they can't be combined with this.
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    /// The timeout for a whole request, from connecting to reading the body.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = Some(user_agent.to_string());
+        self
+    }
+
+    /// Adds a header sent with every request.
+    pub fn default_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn sessionless(mut self, sessionless: Sessionless) -> Self {
+        self.sessionless = Some(sessionless);
+        self
+    }
+
+    pub fn caster(mut self, caster: Caster) -> Self {
+        self.caster = Some(caster);
+        self
+    }
+
+    pub fn resolver(mut self, resolver_url: &str) -> Self {
+        self.resolver_url = Some(resolver_url.to_string());
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
+    /// See `BDO::with_signed_public_bdos`.
+    pub fn signed_public_bdos(mut self, signed_public_bdos: bool) -> Self {
+        self.signed_public_bdos = signed_public_bdos;
+        self
+    }
+
+    /// See `BDO::with_teleport_content_hashes`.
+    pub fn teleport_content_hashes(mut self, teleport_content_hashes: bool) -> Self {
+        self.teleport_content_hashes = teleport_content_hashes;
+        self
+    }
+
+    /// See `BDO::with_skip_unchanged_writes`.
+    pub fn skip_unchanged_writes(mut self, skip_unchanged_writes: bool) -> Self {
+        self.skip_unchanged_writes = skip_unchanged_writes;
+        self
+    }
+
+    /// See `BDO::with_cas_attempts`.
+    pub fn cas_attempts(mut self, cas_attempts: u32) -> Self {
+        self.cas_attempts = Some(cas_attempts);
+        self
+    }
+
+    /// See `BDO::with_cache`.
+    pub fn cache(mut self, store: impl CacheStore + 'static, policy: CachePolicy) -> Self {
+        self.cache = Some((Arc::new(store), policy));
+        self
+    }
+
+    /// Where `allyabase://` base and resolver URLs point.
+    pub fn service_table(mut self, service_table: ServiceTable) -> Self {
+        self.service_table = Some(service_table);
+        self
+    }
+
+    /// Validates the configuration and builds the client.
+    pub fn build(self) -> Result<BDO, BdoError> {
+        let service_table = self.service_table.unwrap_or_default();
+        let base_url = join_base_url(
+            &translate_url(self.base_url.as_deref().unwrap_or(DEFAULT_BASE_URL), &service_table)?,
+            self.path_prefix.as_deref(),
+        )?;
+        let resolver_url = match &self.resolver_url {
+            Some(resolver_url) => Some(join_base_url(&translate_url(resolver_url, &service_table)?, None)?),
+            None => None,
+        };
+
+        let customizes_client = self.connect_timeout.is_some() || self.timeout.is_some()
+            || self.user_agent.is_some() || !self.headers.is_empty();
+        let client = match self.client {
+            Some(_) if customizes_client => {
+                return Err(BdoError::Config("timeouts, user agent and default headers can't be set on a provided reqwest::Client".to_string()));
+            },
+            Some(client) => client,
+            None => {
+                let mut headers = HeaderMap::new();
+                for (name, value) in &self.headers {
+                    let name = HeaderName::from_bytes(name.as_bytes())
+                        .map_err(|_| BdoError::Config(format!("invalid header name: {}", name)))?;
+                    let value = HeaderValue::from_str(value)
+                        .map_err(|_| BdoError::Config(format!("invalid value for header {}", name)))?;
+                    headers.append(name, value);
+                }
+
+                let mut builder = Client::builder().default_headers(headers);
+                if let Some(connect_timeout) = self.connect_timeout {
+                    builder = builder.connect_timeout(connect_timeout);
+                }
+                if let Some(timeout) = self.timeout {
+                    builder = builder.timeout(timeout);
+                }
+                if let Some(user_agent) = &self.user_agent {
+                    builder = builder.user_agent(user_agent);
+                }
+                builder.build()
+                    .map_err(|err| BdoError::Config(format!("could not build the HTTP client: {}", err)))?
+            },
+        };
+
+        Ok(BDO {
+            base_url,
+            client,
+            sessionless: self.sessionless.unwrap_or_else(Sessionless::new),
+            caster: self.caster,
+            resolver_url,
+            retry_policy: self.retry_policy.unwrap_or_default(),
+            signed_public_bdos: self.signed_public_bdos,
+            skip_unchanged_writes: self.skip_unchanged_writes,
+            teleport_content_hashes: self.teleport_content_hashes,
+            written_bdos: Default::default(),
+            cas_attempts: self.cas_attempts.unwrap_or(DEFAULT_CAS_ATTEMPTS).max(1),
+            cache: self.cache.map(|(store, policy)| Arc::new(BdoCache::new(store, policy))),
+        })
+    }
+}
+
+/// Parses a base URL, appends an optional path prefix, and makes sure the
+/// result ends in a slash so routes can be appended to it.
+pub(crate) fn join_base_url(base_url: &str, path_prefix: Option<&str>) -> Result<String, BdoError> {
+    let mut url = Url::parse(base_url)
+        .map_err(|err| BdoError::Config(format!("invalid base URL {}: {}", base_url, err)))?;
+
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(BdoError::Config(format!("base URL {} must be http or https", base_url)));
+    }
+    if url.cannot_be_a_base() || url.host_str().is_none() {
+        return Err(BdoError::Config(format!("base URL {} has no host", base_url)));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(BdoError::Config(format!("base URL {} can't have a query or fragment", base_url)));
+    }
+
+    let segments: Vec<String> = url.path().split('/')
+        .chain(path_prefix.unwrap_or_default().split('/'))
+        .filter(|segment| !segment.is_empty())
+        .map(str::to_string)
+        .collect();
+    let path = if segments.is_empty() {
+        "/".to_string()
+    } else {
+        format!("/{}/", segments.join("/"))
+    };
+    url.set_path(&path);
+
+    Ok(url.to_string())
+}
@@ -0,0 +1,504 @@
+//! An optional read cache for `get_bdo`, `get_public_bdo`, `get_bases` and
+//! `get_spellbooks`, with an offline fallback.
+//!
+//! Responses are cached as the server sent them, keyed by the server, the
+//! client's pubKey, and uuid and hash (or pubKey for public BDOs), and go through the same checks as a network
+//! response on every read, so a signed public BDO is verified even when it
+//! comes from disk. This client's own writes drop the entries they touch.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use sessionless::hex::IntoHex;
+use crate::retry::Operation;
+use crate::{BdoError, BDO};
+
+/// How long cached reads are used, and what happens when they run out.
+#[derive(Clone, Debug)]
+pub struct CachePolicy {
+    /// How long an entry is served without asking the server.
+    pub ttl: Duration,
+    /// How long past `ttl` an entry is still served while a fresh copy is
+    /// fetched in the background.
+    pub stale_while_revalidate: Duration,
+    /// Serves any cached copy, however old, when the server can't be reached.
+    pub offline_fallback: bool,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        CachePolicy {
+            ttl: Duration::from_secs(60),
+            stale_while_revalidate: Duration::from_secs(300),
+            offline_fallback: true,
+        }
+    }
+}
+
+impl CachePolicy {
+    pub fn new(ttl: Duration) -> Self {
+        CachePolicy {
+            ttl,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_stale_while_revalidate(mut self, stale_while_revalidate: Duration) -> Self {
+        self.stale_while_revalidate = stale_while_revalidate;
+        self
+    }
+
+    pub fn with_offline_fallback(mut self, offline_fallback: bool) -> Self {
+        self.offline_fallback = offline_fallback;
+        self
+    }
+}
+
+/// A cached response and when it was fetched.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct CacheEntry {
+    pub value: Value,
+    /// Milliseconds since the Unix epoch.
+    pub stored_at: u64,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64
+}
+
+impl CacheEntry {
+    pub fn new(value: Value) -> Self {
+        CacheEntry { value, stored_at: now_millis() }
+    }
+
+    pub fn age(&self) -> Duration {
+        Duration::from_millis(now_millis().saturating_sub(self.stored_at))
+    }
+}
+
+/// Somewhere to keep cached responses. A store that fails to read or write
+/// only costs a trip to the server, so errors are plain `io::Error`s.
+pub trait CacheStore: Send + Sync {
+    fn load(&self, key: &str) -> std::io::Result<Option<CacheEntry>>;
+    fn save(&self, key: &str, entry: &CacheEntry) -> std::io::Result<()>;
+    fn remove(&self, key: &str) -> std::io::Result<()>;
+    fn clear(&self) -> std::io::Result<()>;
+}
+
+/// Keeps entries for as long as the process runs.
+#[derive(Default)]
+pub struct MemoryCacheStore {
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl MemoryCacheStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CacheStore for MemoryCacheStore {
+    fn load(&self, key: &str) -> std::io::Result<Option<CacheEntry>> {
+        Ok(self.entries.lock().expect("cache lock").get(key).cloned())
+    }
+
+    fn save(&self, key: &str, entry: &CacheEntry) -> std::io::Result<()> {
+        self.entries.lock().expect("cache lock").insert(key.to_string(), entry.clone());
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> std::io::Result<()> {
+        self.entries.lock().expect("cache lock").remove(key);
+        Ok(())
+    }
+
+    fn clear(&self) -> std::io::Result<()> {
+        self.entries.lock().expect("cache lock").clear();
+        Ok(())
+    }
+}
+
+/// Keeps each entry in its own JSON file, so the cache survives restarts
+/// and can be read offline. Private BDOs are written as they are, so put
+/// the directory somewhere only the app can read.
+pub struct FileCacheStore {
+    dir: PathBuf,
+}
+
+impl FileCacheStore {
+    /// Entries are kept in `dir`, which is created on the first save.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FileCacheStore { dir: dir.into() }
+    }
+
+    /// The file a key's entry lives in, named by the key's SHA-256.
+    pub fn path_for(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.bdocache", hex::encode(Sha256::digest(key.as_bytes()))))
+    }
+}
+
+impl CacheStore for FileCacheStore {
+    fn load(&self, key: &str) -> std::io::Result<Option<CacheEntry>> {
+        let contents = match fs::read(self.path_for(key)) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        serde_json::from_slice(&contents).map(Some).map_err(std::io::Error::other)
+    }
+
+    /// Writes through a temporary file of its own, created fresh with mode
+    /// 0600, so concurrent saves of one key never share a file and no one
+    /// else can read it.
+    fn save(&self, key: &str, entry: &CacheEntry) -> std::io::Result<()> {
+        let contents = serde_json::to_vec(entry).map_err(std::io::Error::other)?;
+        let path = self.path_for(key);
+        let tmp = path.with_extension(format!("{}-{:016x}.tmp", std::process::id(), rand::random::<u64>()));
+
+        fs::create_dir_all(&self.dir)?;
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let written = options.open(&tmp)
+            .and_then(|mut file| file.write_all(&contents))
+            .and_then(|_| fs::rename(&tmp, path));
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        written
+    }
+
+    fn remove(&self, key: &str) -> std::io::Result<()> {
+        match fs::remove_file(self.path_for(key)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn clear(&self) -> std::io::Result<()> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "bdocache") {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where a read's value came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Freshness {
+    /// Fetched from the server just now.
+    Live,
+    /// From the cache, within its TTL.
+    Fresh,
+    /// From the cache past its TTL; a fresh copy is being fetched in the background.
+    Stale,
+    /// From the cache because the client is offline or the server couldn't be reached.
+    Offline,
+}
+
+/// A read's value, with how fresh it is.
+#[derive(Clone, Debug)]
+pub struct Cached<T> {
+    pub value: T,
+    pub freshness: Freshness,
+    /// How long ago the value was fetched from the server.
+    pub age: Duration,
+}
+
+impl<T> Cached<T> {
+    /// Whether the value may be out of date.
+    pub fn is_stale(&self) -> bool {
+        matches!(self.freshness, Freshness::Stale | Freshness::Offline)
+    }
+
+    pub fn into_value(self) -> T {
+        self.value
+    }
+
+    pub(crate) fn map<U>(self, f: impl FnOnce(T) -> U) -> Cached<U> {
+        Cached {
+            value: f(self.value),
+            freshness: self.freshness,
+            age: self.age,
+        }
+    }
+}
+
+pub(crate) struct BdoCache {
+    store: Arc<dyn CacheStore>,
+    policy: CachePolicy,
+    offline: AtomicBool,
+    /// Keys with a background refresh under way.
+    refreshing: Mutex<HashSet<String>>,
+    /// Bumped whenever a key is invalidated, so a fetch that started before
+    /// the invalidation doesn't put the old response back.
+    generations: Mutex<Generations>,
+    /// Background refreshes that may still be running.
+    refreshes: Mutex<Vec<tokio::task::JoinHandle<()>>>,
+}
+
+/// Takes a key off `BdoCache::refreshing` when its refresh ends, however
+/// it ends.
+struct RefreshGuard {
+    cache: Arc<BdoCache>,
+    key: String,
+}
+
+impl Drop for RefreshGuard {
+    fn drop(&mut self) {
+        if let Ok(mut refreshing) = self.cache.refreshing.lock() {
+            refreshing.remove(&self.key);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Generations {
+    cleared: u64,
+    keys: HashMap<String, u64>,
+}
+
+impl Generations {
+    fn of(&self, key: &str) -> (u64, u64) {
+        (self.cleared, self.keys.get(key).copied().unwrap_or_default())
+    }
+}
+
+pub(crate) fn bdo_key(uuid: &str, hash: &str) -> String {
+    format!("bdo/{}/{}", uuid, hash)
+}
+
+pub(crate) fn public_bdo_key(pub_key: &str) -> String {
+    format!("public/{}", pub_key)
+}
+
+pub(crate) fn bases_key(uuid: &str, hash: &str) -> String {
+    format!("bases/{}/{}", uuid, hash)
+}
+
+pub(crate) fn spellbooks_key(uuid: &str, hash: &str) -> String {
+    format!("spellbooks/{}/{}", uuid, hash)
+}
+
+fn decode<T: DeserializeOwned>(value: Value) -> Result<T, BdoError> {
+    T::deserialize(&value).map_err(|source| BdoError::Decode { source, body: value.to_string() })
+}
+
+impl BdoCache {
+    pub(crate) fn new(store: Arc<dyn CacheStore>, policy: CachePolicy) -> Self {
+        BdoCache {
+            store,
+            policy,
+            offline: AtomicBool::new(false),
+            refreshing: Default::default(),
+            generations: Default::default(),
+            refreshes: Default::default(),
+        }
+    }
+
+    fn generation(&self, key: &str) -> (u64, u64) {
+        self.generations.lock().expect("cache lock").of(key)
+    }
+
+    /// Saves a fetched response unless its key was invalidated after the
+    /// fetch started at `generation`.
+    fn save_if_current(&self, key: &str, generation: (u64, u64), value: Value) {
+        let generations = self.generations.lock().expect("cache lock");
+        if generations.of(key) == generation {
+            let _ = self.store.save(key, &CacheEntry::new(value));
+        }
+    }
+
+    fn invalidate(&self, key: &str) {
+        let mut generations = self.generations.lock().expect("cache lock");
+        *generations.keys.entry(key.to_string()).or_default() += 1;
+        let _ = self.store.remove(key);
+    }
+
+    fn clear(&self) {
+        let mut generations = self.generations.lock().expect("cache lock");
+        generations.cleared += 1;
+        generations.keys.clear();
+        let _ = self.store.clear();
+    }
+}
+
+impl BDO {
+    /// Caches `get_bdo`, `get_public_bdo`, `get_bases` and `get_spellbooks`
+    /// in `store`.
+    pub fn with_cache(mut self, store: impl CacheStore + 'static, policy: CachePolicy) -> Self {
+        self.cache = Some(Arc::new(BdoCache::new(Arc::new(store), policy)));
+        self
+    }
+
+    /// In offline mode cached reads never go to the server: they're served
+    /// from the cache, however old, or fail with `BdoError::Offline`.
+    pub fn set_offline(&self, offline: bool) {
+        if let Some(cache) = &self.cache {
+            cache.offline.store(offline, Ordering::SeqCst);
+        }
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.cache.as_ref().is_some_and(|cache| cache.offline.load(Ordering::SeqCst))
+    }
+
+    /// Drops the cached BDO, bases and spellbooks for a uuid and hash.
+    pub fn invalidate_cache(&self, uuid: &str, hash: &str) {
+        self.forget_cached(&[bdo_key(uuid, hash), bases_key(uuid, hash), spellbooks_key(uuid, hash)]);
+    }
+
+    /// Drops every entry in the store, including those of other clients
+    /// sharing it.
+    pub fn clear_cache(&self) {
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+    }
+
+    pub(crate) fn forget_cached(&self, keys: &[String]) {
+        if let Some(cache) = &self.cache {
+            for key in keys {
+                cache.invalidate(&self.scoped_key(key));
+            }
+        }
+    }
+
+    /// Puts the server and the identity a key is read with in front of it,
+    /// so clients sharing a store never see each other's entries.
+    fn scoped_key(&self, key: &str) -> String {
+        format!("{}|{}|{}", self.base_url, self.sessionless.public_key().to_hex(), key)
+    }
+
+    /// Reads through the cache. `url` signs a fresh request URL for each
+    /// attempt.
+    pub(crate) async fn cached_read<T, U>(&self, key: String, url: U) -> Result<Cached<T>, BdoError>
+    where
+        T: DeserializeOwned,
+        U: Fn() -> String,
+    {
+        let Some(cache) = &self.cache else {
+            let value = self.uncached_read(url).await?;
+            return Ok(Cached { value, freshness: Freshness::Live, age: Duration::ZERO });
+        };
+
+        let (name, key) = (key.clone(), self.scoped_key(&key));
+        // An unreadable entry is just a miss.
+        let entry = cache.store.load(&key).ok().flatten();
+        let cached = |entry: CacheEntry, freshness| -> Result<Cached<T>, BdoError> {
+            let age = entry.age();
+            Ok(Cached { value: decode(entry.value)?, freshness, age })
+        };
+
+        if cache.offline.load(Ordering::SeqCst) {
+            return match entry {
+                Some(entry) => cached(entry, Freshness::Offline),
+                None => Err(BdoError::Offline(name)),
+            };
+        }
+        if let Some(entry) = &entry {
+            let age = entry.age();
+            if age <= cache.policy.ttl {
+                return cached(entry.clone(), Freshness::Fresh);
+            }
+            if age <= cache.policy.ttl + cache.policy.stale_while_revalidate {
+                self.refresh_in_background(cache, &key, url());
+                return cached(entry.clone(), Freshness::Stale);
+            }
+        }
+
+        let generation = cache.generation(&key);
+        match self.fetch_raw(&url).await {
+            Ok(value) => {
+                cache.save_if_current(&key, generation, value.clone());
+                Ok(Cached { value: decode(value)?, freshness: Freshness::Live, age: Duration::ZERO })
+            },
+            Err(err) if cache.policy.offline_fallback && self.retry_policy.is_transient(&err) => match entry {
+                Some(entry) => cached(entry, Freshness::Offline),
+                None => Err(err),
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Reads straight from the server, neither using nor filling the cache.
+    pub(crate) async fn uncached_read<T, U>(&self, url: U) -> Result<T, BdoError>
+    where
+        T: DeserializeOwned,
+        U: Fn() -> String,
+    {
+        decode(self.fetch_raw(&url).await?)
+    }
+
+    async fn fetch_raw(&self, url: &impl Fn() -> String) -> Result<Value, BdoError> {
+        self.retrying(Operation::Read, || async {
+            let res = self.get(&url()).await?;
+            Self::parse::<Value>(res).await
+        }).await
+    }
+
+    /// Fetches `url` on the current tokio runtime and caches the response.
+    /// The request is signed now, so the refresh doesn't need the client.
+    fn refresh_in_background(&self, cache: &Arc<BdoCache>, key: &str, url: String) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        if !cache.refreshing.lock().expect("cache lock").insert(key.to_string()) {
+            return;
+        }
+
+        let client = self.client.clone();
+        let generation = cache.generation(key);
+        let guard = RefreshGuard { cache: cache.clone(), key: key.to_string() };
+        let refresh = runtime.spawn(async move {
+            crate::trace::request("GET", &url, None);
+            if let Ok(res) = client.get(&url).send().await {
+                let status = res.status();
+                if let Ok(value) = res.text().await.map_err(BdoError::from).and_then(|body| Self::parse_body::<Value>(status, body)) {
+                    guard.cache.save_if_current(&guard.key, generation, value);
+                }
+            }
+        });
+
+        let mut refreshes = cache.refreshes.lock().expect("cache lock");
+        refreshes.retain(|refresh| !refresh.is_finished());
+        refreshes.push(refresh);
+    }
+
+    /// Waits for every background refresh started so far, e.g. before
+    /// shutting down so a refreshed entry isn't lost.
+    pub async fn finish_refreshes(&self) {
+        let Some(cache) = &self.cache else {
+            return;
+        };
+        let refreshes = std::mem::take(&mut *cache.refreshes.lock().expect("cache lock"));
+        for refresh in refreshes {
+            let _ = refresh.await;
+        }
+    }
+}
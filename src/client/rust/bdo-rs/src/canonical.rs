@@ -0,0 +1,169 @@
+//! Canonical JSON, so the same value always serializes to the same bytes
+//! whatever order its keys were built or received in, and content hashes
+//! over it.
+//!
+//! This follows RFC 8785 (JCS): object keys are sorted by their UTF-16 code
+//! units, no whitespace is written, strings escape only what JSON requires,
+//! and numbers are written the way JavaScript writes them, with the shortest
+//! digits that round-trip, so `1.0` and `1` are the same number. Integers
+//! past 2^53 go through a double like everything else, so
+//! `1152921504606846976` and `1152921504606846976.0` are both
+//! `1152921504606847000`.
+
+use serde_json::{Number, Value};
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use crate::{BDO, BdoError};
+
+pub fn to_canonical_string(value: &Value) -> String {
+    let mut out = String::new();
+    write_canonical(value, &mut out);
+    out
+}
+
+/// Serializes `value` and writes it canonically.
+pub fn to_canonical_string_from<T: serde::Serialize + ?Sized>(value: &T) -> Result<String, BdoError> {
+    crate::typed::to_bdo(value).map(|value| to_canonical_string(&value))
+}
+
+/// The hex SHA-256 of a value's canonical JSON. Equal values hash equally,
+/// however their keys are ordered or their numbers written.
+pub fn content_hash(value: &Value) -> String {
+    hex::encode(Sha256::digest(to_canonical_string(value).as_bytes()))
+}
+
+fn compare_keys(a: &str, b: &str) -> Ordering {
+    a.encode_utf16().cmp(b.encode_utf16())
+}
+
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Array(items) => {
+            out.push('[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        },
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|(a, _), (b, _)| compare_keys(a, b));
+
+            out.push('{');
+            for (index, (key, item)) in entries.into_iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(item, out);
+            }
+            out.push('}');
+        },
+        Value::Number(number) => out.push_str(&canonical_number(number)),
+        scalar => out.push_str(&scalar.to_string()),
+    }
+}
+
+/// The largest integer JavaScript represents exactly, 2^53.
+const MAX_EXACT_INTEGER: u64 = 1 << 53;
+
+/// Writes a number like JavaScript's `Number.prototype.toString`.
+fn canonical_number(number: &Number) -> String {
+    let exact = match (number.as_u64(), number.as_i64()) {
+        (Some(unsigned), _) => unsigned <= MAX_EXACT_INTEGER,
+        (None, Some(signed)) => signed.unsigned_abs() <= MAX_EXACT_INTEGER,
+        (None, None) => false,
+    };
+    if exact {
+        return number.to_string();
+    }
+
+    let float = number.as_f64().unwrap_or_default();
+    if float == 0.0 {
+        return "0".to_string();
+    }
+
+    // `{:e}` gives the shortest digits that round-trip, e.g. `1.152921504606847e18`.
+    let exponential = format!("{:e}", float.abs());
+    let (mantissa, exponent) = exponential.split_once('e').expect("{:e} has an exponent");
+    let digits = mantissa.replace('.', "");
+    let k = digits.len() as i32;
+    // The decimal point goes after the first `n` digits.
+    let n = exponent.parse::<i32>().expect("{:e} has an integer exponent") + 1;
+
+    let written = if k <= n && n <= 21 {
+        format!("{}{}", digits, "0".repeat((n - k) as usize))
+    } else if 0 < n && n <= 21 {
+        format!("{}.{}", &digits[..n as usize], &digits[n as usize..])
+    } else if -6 < n && n <= 0 {
+        format!("0.{}{}", "0".repeat(-n as usize), digits)
+    } else {
+        let sign = if n > 0 { "+" } else { "-" };
+        match digits.split_at(1) {
+            (first, "") => format!("{}e{}{}", first, sign, (n - 1).abs()),
+            (first, rest) => format!("{}.{}e{}{}", first, rest, sign, (n - 1).abs()),
+        }
+    };
+
+    if float < 0.0 {
+        format!("-{}", written)
+    } else {
+        written
+    }
+}
+
+/// The content hashes of the last BDO this client wrote per uuid and hash.
+#[derive(Debug, Default)]
+pub(crate) struct WrittenBdos(Mutex<HashMap<(String, String), String>>);
+
+impl WrittenBdos {
+    fn key(uuid: &str, hash: &str) -> (String, String) {
+        (uuid.to_string(), hash.to_string())
+    }
+}
+
+impl BDO {
+    /// Makes `update_bdo` return without a request when the BDO and its
+    /// public flag are the same as the last ones this client wrote for that
+    /// uuid and hash. Writes made elsewhere aren't seen, so only turn this on
+    /// when this client is the BDO's only writer.
+    pub fn with_skip_unchanged_writes(mut self, skip_unchanged_writes: bool) -> Self {
+        self.skip_unchanged_writes = skip_unchanged_writes;
+        self
+    }
+
+    pub fn skips_unchanged_writes(&self) -> bool {
+        self.skip_unchanged_writes
+    }
+
+    /// The content hash of a write, when unchanged writes are skipped.
+    pub(crate) fn write_fingerprint(&self, bdo: &Value, is_public: &bool) -> Option<String> {
+        self.skip_unchanged_writes.then(|| content_hash(&serde_json::json!({ "bdo": bdo, "pub": is_public })))
+    }
+
+    pub(crate) fn is_unchanged(&self, uuid: &str, hash: &str, fingerprint: Option<&str>) -> bool {
+        let Some(fingerprint) = fingerprint else {
+            return false;
+        };
+
+        self.written_bdos.0.lock().expect("written BDOs lock")
+            .get(&WrittenBdos::key(uuid, hash))
+            .is_some_and(|written| written == fingerprint)
+    }
+
+    pub(crate) fn remember_write(&self, uuid: &str, hash: &str, fingerprint: Option<String>) {
+        if let Some(fingerprint) = fingerprint {
+            self.written_bdos.0.lock().expect("written BDOs lock").insert(WrittenBdos::key(uuid, hash), fingerprint);
+        }
+    }
+
+    pub(crate) fn forget_writes(&self, uuid: &str) {
+        self.written_bdos.0.lock().expect("written BDOs lock").retain(|(written_uuid, _), _| written_uuid != uuid);
+    }
+}
@@ -0,0 +1,115 @@
+//! Compare-and-swap updates, so two clients editing the same BDO don't
+//! silently overwrite each other.
+//!
+//! The BDO server has no conditional write, so a swap reads the stored BDO
+//! again just before writing and compares content hashes. That catches any
+//! write made while the update was being worked out, but not one that lands
+//! between that last read and the write itself.
+
+use serde_json::Value;
+use crate::canonical::content_hash;
+use crate::envelope::ENVELOPE_KEY;
+use crate::structs::BDOUser;
+use crate::{BdoError, BDO};
+
+/// How many times `update_bdo_with` tries before giving up with a conflict.
+pub const DEFAULT_CAS_ATTEMPTS: u32 = 3;
+
+/// Both sides of an update that lost a race.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BdoConflict {
+    /// The BDO the update started from.
+    pub base: Value,
+    /// The BDO the update meant to write.
+    pub ours: Value,
+    /// The BDO stored now, written by someone else since `base` was read.
+    pub theirs: Value,
+}
+
+impl BDO {
+    /// How many times `update_bdo_with` and `update_bdo_with_merge` read,
+    /// update and try to swap before returning `BdoError::Conflict`.
+    pub fn with_cas_attempts(mut self, cas_attempts: u32) -> Self {
+        self.cas_attempts = cas_attempts.max(1);
+        self
+    }
+
+    pub fn cas_attempts(&self) -> u32 {
+        self.cas_attempts
+    }
+
+    /// The stored BDO and its content hash. A signed BDO comes back without
+    /// its envelope, since every write signs it again. This always asks the
+    /// server, since a cached copy can't tell whether someone else wrote.
+    pub async fn get_bdo_versioned(&self, uuid: &str, hash: &str) -> Result<(Value, String), BdoError> {
+        let mut bdo = self.uncached_read::<BDOUser, _>(|| self.bdo_url(uuid, hash)).await?.bdo;
+        if self.signed_public_bdos {
+            if let Some(object) = bdo.as_object_mut() {
+                object.remove(ENVELOPE_KEY);
+            }
+        }
+
+        let version = content_hash(&bdo);
+        Ok((bdo, version))
+    }
+
+    /// Writes `bdo` only if what's stored is still `base`, and returns
+    /// `BdoError::Conflict` with the stored BDO otherwise.
+    pub async fn compare_and_swap(&self, uuid: &str, hash: &str, base: &Value, bdo: &Value, is_public: &bool) -> Result<BDOUser, BdoError> {
+        let (current, version) = self.get_bdo_versioned(uuid, hash).await?;
+        if version != content_hash(base) {
+            return Err(BdoError::Conflict(Box::new(BdoConflict {
+                base: base.clone(),
+                ours: bdo.clone(),
+                theirs: current,
+            })));
+        }
+
+        self.update_bdo(uuid, hash, bdo, is_public).await
+    }
+
+    /// Reads the BDO, runs `update` on it and swaps the result in. When
+    /// someone else wrote in between, `update` runs again on their version,
+    /// up to `cas_attempts` times.
+    pub async fn update_bdo_with<F>(&self, uuid: &str, hash: &str, is_public: &bool, mut update: F) -> Result<BDOUser, BdoError>
+    where
+        F: FnMut(&Value) -> Result<Value, BdoError>,
+    {
+        self.swap_until_settled(uuid, hash, is_public, |base, _| update(base)).await
+    }
+
+    /// Like `update_bdo_with`, but a conflict goes to `merge`, which gets
+    /// both versions and returns the BDO to try writing instead.
+    pub async fn update_bdo_with_merge<F, M>(&self, uuid: &str, hash: &str, is_public: &bool, mut update: F, mut merge: M) -> Result<BDOUser, BdoError>
+    where
+        F: FnMut(&Value) -> Result<Value, BdoError>,
+        M: FnMut(&BdoConflict) -> Result<Value, BdoError>,
+    {
+        self.swap_until_settled(uuid, hash, is_public, |base, conflict| match conflict {
+            Some(conflict) => merge(conflict),
+            None => update(base),
+        }).await
+    }
+
+    /// Works out a BDO with `next` and swaps it in, feeding each conflict
+    /// back to `next` until a swap lands or the attempts run out.
+    async fn swap_until_settled<F>(&self, uuid: &str, hash: &str, is_public: &bool, mut next: F) -> Result<BDOUser, BdoError>
+    where
+        F: FnMut(&Value, Option<&BdoConflict>) -> Result<Value, BdoError>,
+    {
+        let (mut base, _) = self.get_bdo_versioned(uuid, hash).await?;
+        let mut conflict: Option<BdoConflict> = None;
+        let mut attempts = 1;
+        loop {
+            let ours = next(&base, conflict.as_ref())?;
+            match self.compare_and_swap(uuid, hash, &base, &ours, is_public).await {
+                Err(BdoError::Conflict(found)) if attempts < self.cas_attempts => {
+                    base = found.theirs.clone();
+                    conflict = Some(*found);
+                    attempts += 1;
+                },
+                result => return result,
+            }
+        }
+    }
+}
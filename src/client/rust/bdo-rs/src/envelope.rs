@@ -0,0 +1,123 @@
+//! Signed public BDOs.
+//!
+//! With signing turned on, a public BDO is saved with an envelope under
+//! `ENVELOPE_KEY` holding its owner's signature over the canonical JSON of
+//! the rest of the BDO. Public reads check the envelope against the pubKey
+//! they expected and hand back the BDO without it, so a BDO server can't
+//! serve content its owner never signed.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sessionless::hex::{FromHex, IntoHex};
+use sessionless::{PublicKey, Signature};
+use crate::canonical::to_canonical_string;
+use crate::structs::BDOUser;
+use crate::{BdoError, BDO};
+
+/// The key a signed BDO keeps its envelope under.
+pub const ENVELOPE_KEY: &str = "bdoSignature";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct Envelope {
+    pub pub_key: String,
+    pub timestamp: String,
+    pub signature: String,
+}
+
+/// What an envelope signs: its timestamp followed by the canonical JSON of
+/// the BDO without the envelope.
+pub fn signing_message(bdo: &Value, timestamp: &str) -> String {
+    let mut unsigned = bdo.clone();
+    if let Some(object) = unsigned.as_object_mut() {
+        object.remove(ENVELOPE_KEY);
+    }
+
+    format!("{}{}", timestamp, to_canonical_string(&unsigned))
+}
+
+fn unverified(reason: &str) -> BdoError {
+    BdoError::Unverified(reason.to_string())
+}
+
+impl BDO {
+    /// Signs public BDOs on `create_user`/`update_bdo` and verifies them on
+    /// `get_public_bdo`, `get_bdo_by_emojicode` and `get_bdo_by_short_code`.
+    pub fn with_signed_public_bdos(mut self, signed_public_bdos: bool) -> Self {
+        self.signed_public_bdos = signed_public_bdos;
+        self
+    }
+
+    pub fn signs_public_bdos(&self) -> bool {
+        self.signed_public_bdos
+    }
+
+    /// Adds an envelope signed with this client's key. Only objects can
+    /// carry one.
+    pub fn sign_bdo(&self, bdo: &Value) -> Result<Value, BdoError> {
+        if !bdo.is_object() {
+            return Err(BdoError::Config("only object BDOs can be signed".to_string()));
+        }
+
+        let timestamp = Self::get_timestamp();
+        let envelope = Envelope {
+            pub_key: self.sessionless.public_key().to_hex(),
+            signature: self.sessionless.sign(signing_message(bdo, &timestamp)).to_hex(),
+            timestamp,
+        };
+
+        let mut signed = bdo.clone();
+        signed[ENVELOPE_KEY] = serde_json::to_value(envelope).expect("envelopes serialize");
+        Ok(signed)
+    }
+
+    /// Checks that `bdo` was signed by `expected_pub_key` and returns it
+    /// without its envelope.
+    pub fn verify_bdo(&self, mut bdo: Value, expected_pub_key: &str) -> Result<Value, BdoError> {
+        let envelope = bdo.as_object_mut()
+            .and_then(|object| object.remove(ENVELOPE_KEY))
+            .ok_or_else(|| unverified("the BDO isn't signed"))?;
+        let envelope: Envelope = serde_json::from_value(envelope)
+            .map_err(|_| unverified("the BDO's envelope is malformed"))?;
+
+        let expected = PublicKey::from_hex(expected_pub_key).map_err(|_| unverified("the expected pubKey isn't a public key"))?;
+        let signer = PublicKey::from_hex(&envelope.pub_key).map_err(|_| unverified("the envelope's pubKey isn't a public key"))?;
+        if signer != expected {
+            return Err(unverified("the BDO was signed by a different pubKey"));
+        }
+
+        let signature = Signature::from_hex(&envelope.signature).map_err(|_| unverified("the envelope's signature is malformed"))?;
+        self.sessionless.verify(signing_message(&bdo, &envelope.timestamp), &signer, &signature)
+            .map_err(|_| unverified("the signature doesn't match the BDO"))?;
+
+        Ok(bdo)
+    }
+
+    /// Signs `bdo` if it's public and signing is on.
+    pub(crate) fn sign_if_public(&self, bdo: &Value, is_public: &bool) -> Result<Option<Value>, BdoError> {
+        if self.signed_public_bdos && *is_public {
+            self.sign_bdo(bdo).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Verifies a public BDO if signing is on.
+    pub(crate) fn verify_if_signing(&self, bdo: Value, expected_pub_key: &str) -> Result<Value, BdoError> {
+        if self.signed_public_bdos {
+            self.verify_bdo(bdo, expected_pub_key)
+        } else {
+            Ok(bdo)
+        }
+    }
+
+    /// Drops the envelope from a BDO the server echoed back after a write.
+    pub(crate) fn strip_envelope(&self, mut user: BDOUser) -> BDOUser {
+        if self.signed_public_bdos {
+            if let Some(object) = user.bdo.as_object_mut() {
+                object.remove(ENVELOPE_KEY);
+            }
+        }
+        user
+    }
+}
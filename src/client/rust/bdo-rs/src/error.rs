@@ -0,0 +1,178 @@
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt::{Debug, Display, Formatter};
+use crate::cas::BdoConflict;
+
+/// Error body shapes the BDO server sends back.
+///
+/// The REST routes answer with either `{"code", "message"}` or `{"error"}`,
+/// and MAGIC spells with `{"success": false, "error"}`, so every field is optional.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ErrorBody {
+    #[serde(default)]
+    pub success: Option<bool>,
+    #[serde(default)]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl ErrorBody {
+    /// The most descriptive text the server gave us, if any.
+    pub fn text(&self) -> Option<&str> {
+        self.error.as_deref().or(self.message.as_deref())
+    }
+}
+
+/// Possible errors when talking to a BDO server.
+#[derive(Debug)]
+pub enum BdoError {
+    /// The request never got a response (connection, TLS, timeout, ...).
+    Transport(reqwest::Error),
+    /// The server answered with an error we don't have a better variant for.
+    Status { status: StatusCode, body: ErrorBody },
+    /// The signature, timestamp or hash was rejected.
+    Auth { status: StatusCode, message: String },
+    /// The user, BDO, emojicode or short code does not exist.
+    NotFound { status: StatusCode, message: String },
+    /// A public BDO is already locked to a different pubKey. MAGIC says so
+    /// outright; over REST the server answers `Auth error`, so `update_bdo`
+    /// reads the stored BDO back to tell the two apart.
+    PubKeyConflict { status: StatusCode, message: String },
+    /// A MAGIC spell answered `{"success": false, "error"}`.
+    Spell { status: StatusCode, message: String },
+    /// The response was not the JSON we expected.
+    Decode { source: serde_json::Error, body: String },
+    /// A BDO didn't fit, or couldn't be made from, the requested Rust type.
+    /// `path` points at the offending field, e.g. `levels[2].name`.
+    Typed { path: String, source: serde_json::Error },
+    /// A signed public BDO was unsigned, or its signature didn't check out.
+    Unverified(String),
+    /// A compare-and-swap update found the BDO changed under it, and ran
+    /// out of attempts or had no way to merge.
+    Conflict(Box<BdoConflict>),
+    /// The client is offline and has no cached copy of what was asked for.
+    Offline(String),
+    /// A JSON Patch didn't apply, e.g. a `test` failed or a path was missing.
+    Patch(String),
+    /// Teleported content wasn't valid or couldn't be parsed.
+    Teleport(String),
+    /// The client is missing configuration the operation needs.
+    Config(String),
+    /// A key store couldn't load or save an identity.
+    KeyStore(String),
+}
+
+impl BdoError {
+    /// Inspects a response and returns the error it describes, if any.
+    ///
+    /// The node server sometimes reports failures with a `200` status and an
+    /// `{"error"}` body, so the body is checked even for successful statuses.
+    pub fn from_response(status: StatusCode, body: &str) -> Option<Self> {
+        let parsed = serde_json::from_str::<ErrorBody>(body).ok()
+            .filter(|error_body| error_body.error.is_some() || (error_body.code.is_some() && error_body.message.is_some()));
+
+        let error_body = match parsed {
+            Some(error_body) => error_body,
+            None if status.is_success() => return None,
+            None => ErrorBody {
+                message: status.canonical_reason().map(str::to_string),
+                ..ErrorBody::default()
+            },
+        };
+
+        let message = error_body.text().unwrap_or_default().to_string();
+        let lowered = message.to_lowercase();
+
+        if lowered.contains("different pubkey") {
+            return Some(Self::PubKeyConflict { status, message });
+        }
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN
+            || lowered.contains("auth error") || lowered.contains("no time like the present") {
+            return Some(Self::Auth { status, message });
+        }
+        if status == StatusCode::NOT_FOUND || lowered.contains("not found") {
+            return Some(Self::NotFound { status, message });
+        }
+
+        if error_body.success == Some(false) {
+            return Some(Self::Spell { status, message });
+        }
+
+        Some(Self::Status { status, body: error_body })
+    }
+
+    /// The HTTP status of the response, when there was one.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::Transport(err) => err.status(),
+            Self::Status { status, .. }
+            | Self::Auth { status, .. }
+            | Self::NotFound { status, .. }
+            | Self::PubKeyConflict { status, .. }
+            | Self::Spell { status, .. } => Some(*status),
+            Self::Decode { .. } | Self::Typed { .. } | Self::Unverified(_) | Self::Conflict(_) | Self::Patch(_) | Self::Offline(_) | Self::Teleport(_) | Self::Config(_) | Self::KeyStore(_) => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for BdoError {
+    fn from(err: reqwest::Error) -> Self {
+        // Request URLs carry signatures in their query strings.
+        let err = Self::Transport(err.without_url());
+        crate::trace::failure(&err);
+        err
+    }
+}
+
+impl Display for BdoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Transport(err) => write!(f, "transport error: {}", err),
+            Self::Status { status, body } => write!(f, "server returned {}: {}", status, body.text().unwrap_or("no message")),
+            Self::Auth { status, message } => write!(f, "auth rejected ({}): {}", status, message),
+            Self::NotFound { status, message } => write!(f, "not found ({}): {}", status, message),
+            Self::PubKeyConflict { status, message } => write!(f, "public BDO pubKey conflict ({}): {}", status, message),
+            Self::Spell { status, message } => write!(f, "spell failed ({}): {}", status, message),
+            Self::Decode { source, .. } => write!(f, "could not decode response: {}", source),
+            Self::Typed { path, source } => write!(f, "BDO field `{}` doesn't match its type: {}", path, source),
+            Self::Unverified(message) => write!(f, "could not verify public BDO: {}", message),
+            Self::Conflict(_) => write!(f, "the BDO changed while it was being updated"),
+            Self::Offline(key) => write!(f, "offline, and nothing is cached for {}", key),
+            Self::Patch(message) => write!(f, "could not apply patch: {}", message),
+            Self::Teleport(message) => write!(f, "teleport failed: {}", message),
+            Self::Config(message) => write!(f, "invalid configuration: {}", message),
+            Self::KeyStore(message) => write!(f, "key store error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for BdoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Transport(err) => Some(err),
+            Self::Decode { source, .. } | Self::Typed { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// JavaScript truthiness, for the flags the server tests with `if`.
+pub(crate) fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(flag) => *flag,
+        Value::Number(number) => number.as_f64().is_some_and(|number| number != 0.0),
+        Value::String(string) => !string.is_empty(),
+        Value::Array(_) | Value::Object(_) => true,
+    }
+}
+
+/// Whether the server refuses a public write from `pub_key` over `stored`:
+/// it does when the stored BDO's `pub` is truthy and its `pubKey` differs.
+pub(crate) fn is_locked_against(stored: &Value, pub_key: Option<&str>) -> bool {
+    stored.get("pub").is_some_and(is_truthy) && stored.get("pubKey").and_then(Value::as_str) != pub_key
+}
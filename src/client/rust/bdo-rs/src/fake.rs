@@ -0,0 +1,508 @@
+//! An in-process stand-in for the node BDO server, so tests can run without
+//! one. Downstream crates get it with the `fake-server` feature.
+//!
+//! It serves the routes `BDO` calls on a local port and checks sessionless
+//! signatures the way the BDO server and continuebee do, so a request the
+//! real server would turn away is turned away here too. Like the node server,
+//! it answers auth failures with a `200` and an `{"error"}` body.
+
+use rand::seq::SliceRandom;
+use serde_json::{json, Map, Value};
+use sessionless::hex::FromHex;
+use sessionless::{PublicKey, Sessionless, Signature};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::error::{is_locked_against, is_truthy};
+use crate::structs::TeleportResponse;
+
+/// How far a request's timestamp may be from the fake's clock, in milliseconds.
+pub const DEFAULT_ALLOWED_TIME_DIFFERENCE: u64 = 300_000;
+
+/// The emoji every fake emojicode starts with, as the node server's default
+/// federation and base emoji.
+pub const EMOJICODE_PREFIX: &str = "💚🌍🔑💎";
+
+const EMOJI_PALETTE: &[&str] = &[
+    "🌟", "🌙", "🌊", "🔥", "🎨", "🎭", "🎯", "🎲", "🎸", "🏆",
+    "🐉", "🐙", "🐝", "🐢", "🐳", "👑", "💡", "💫", "📚", "🚀",
+];
+
+/// A status and a JSON body, or no body at all.
+type Reply = (u16, Option<Value>);
+
+struct State {
+    sessionless: Sessionless,
+    allowed_time_difference: u64,
+    /// Continuebee's users: uuid to pubKey.
+    users: HashMap<String, String>,
+    bdos: HashMap<(String, String), Value>,
+    public_bdos: HashMap<String, Value>,
+    /// Emojicode to pubKey and creation time.
+    emojicodes: HashMap<String, (String, i64)>,
+    short_codes: HashMap<String, String>,
+    short_code_counter: u64,
+    bases: Map<String, Value>,
+    spellbooks: Vec<Value>,
+    templates: HashMap<String, Vec<String>>,
+    teleports: HashMap<String, Value>,
+}
+
+/// A running fake BDO server. It stops when dropped.
+pub struct FakeBdoServer {
+    url: String,
+    state: Arc<Mutex<State>>,
+    stopped: Arc<AtomicBool>,
+}
+
+struct Request {
+    method: String,
+    path: Vec<String>,
+    query: HashMap<String, String>,
+    body: Value,
+}
+
+impl Request {
+    /// A string parameter from the query string, or from the body.
+    fn param(&self, name: &str) -> Option<String> {
+        self.query.get(name).cloned()
+            .or_else(|| self.body.get(name).and_then(Value::as_str).map(str::to_string))
+    }
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as i64
+}
+
+fn random_uuid() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+fn error(status: u16, message: &str) -> Reply {
+    (status, Some(json!({ "error": message })))
+}
+
+fn auth_error() -> Reply {
+    error(200, "Auth error")
+}
+
+fn not_found() -> Reply {
+    error(404, "not found")
+}
+
+impl FakeBdoServer {
+    /// Starts a fake on a free port of `127.0.0.1`.
+    pub fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/", listener.local_addr()?);
+        let state = Arc::new(Mutex::new(State {
+            sessionless: Sessionless::new(),
+            allowed_time_difference: DEFAULT_ALLOWED_TIME_DIFFERENCE,
+            users: HashMap::new(),
+            bdos: HashMap::new(),
+            public_bdos: HashMap::new(),
+            emojicodes: HashMap::new(),
+            short_codes: HashMap::new(),
+            short_code_counter: 0,
+            bases: Map::new(),
+            spellbooks: Vec::new(),
+            templates: HashMap::new(),
+            teleports: HashMap::new(),
+        }));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let accept_state = state.clone();
+        let accept_stopped = stopped.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accept_stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let state = accept_state.clone();
+                std::thread::spawn(move || {
+                    // A connection that goes away mid-request has nobody to answer.
+                    let _ = serve(stream, &state);
+                });
+            }
+        });
+
+        Ok(FakeBdoServer { url, state, stopped })
+    }
+
+    /// The base URL to hand to `BDO::new` or the builder, ending in `/`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Changes how stale a request's timestamp may be before it's answered
+    /// with `no time like the present`.
+    pub fn with_allowed_time_difference(self, millis: u64) -> Self {
+        self.lock().allowed_time_difference = millis;
+        self
+    }
+
+    /// Makes teleporting to `url` succeed with `response`. The fake doesn't
+    /// fetch anything, so a URL nobody registered answers `{"valid": false}`.
+    pub fn add_teleport(&self, url: &str, response: &TeleportResponse) {
+        let mut tag = serde_json::to_value(response).expect("teleport responses serialize");
+        tag["valid"] = json!(true);
+        self.lock().teleports.insert(url.to_string(), tag);
+    }
+
+    /// Gives `pub_key`'s public BDO a short code, numbered like the node
+    /// server's, and returns it. The node server doesn't hand these out on
+    /// writes, so neither does the fake.
+    pub fn assign_short_code(&self, pub_key: &str) -> String {
+        let mut state = self.lock();
+        state.short_code_counter += 1;
+        let short_code = format!("{:09x}", state.short_code_counter);
+        state.short_codes.insert(short_code.clone(), pub_key.to_string());
+        short_code
+    }
+
+    /// The private BDO saved for `uuid` and `hash`.
+    pub fn bdo(&self, uuid: &str, hash: &str) -> Option<Value> {
+        self.lock().bdos.get(&(uuid.to_string(), hash.to_string())).cloned()
+    }
+
+    /// The public BDO saved for `pub_key`.
+    pub fn public_bdo(&self, pub_key: &str) -> Option<Value> {
+        self.lock().public_bdos.get(pub_key).cloned()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("fake server lock")
+    }
+}
+
+impl Drop for FakeBdoServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wakes the accept loop so it sees it's been stopped.
+        if let Some(addr) = self.url.trim_start_matches("http://").strip_suffix('/') {
+            let _ = TcpStream::connect(addr);
+        }
+    }
+}
+
+fn serve(mut stream: TcpStream, state: &Mutex<State>) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let (status, body) = match parse_request(&request_line, &body) {
+        Some(request) => handle(&mut state.lock().expect("fake server lock"), &request),
+        None => error(400, "malformed request"),
+    };
+
+    let body = body.map(|body| body.to_string()).unwrap_or_default();
+    let reply = format!(
+        "HTTP/1.1 {} Fake\r\nContent-Type: application/json; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(reply.as_bytes())?;
+    stream.flush()
+}
+
+fn parse_request(request_line: &str, body: &[u8]) -> Option<Request> {
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let url = reqwest::Url::parse(&format!("http://fake{}", parts.next()?)).ok()?;
+
+    let path = url.path_segments()?
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| urlencoding::decode(segment).map(|decoded| decoded.into_owned()))
+        .collect::<Result<_, _>>()
+        .ok()?;
+    let query = url.query_pairs().into_owned().collect();
+    let body = serde_json::from_slice(body).unwrap_or(Value::Null);
+
+    Some(Request { method, path, query, body })
+}
+
+fn handle(state: &mut State, request: &Request) -> Reply {
+    // The node server checks every request's timestamp, if it has one.
+    let timestamp = request.param("timestamp")
+        .or_else(|| request.body.get("timestamp").and_then(Value::as_i64).map(|timestamp| timestamp.to_string()))
+        .and_then(|timestamp| timestamp.parse::<i64>().ok());
+    if let Some(timestamp) = timestamp {
+        if now_millis().abs_diff(timestamp) > state.allowed_time_difference {
+            return error(200, "no time like the present");
+        }
+    }
+
+    let path: Vec<&str> = request.path.iter().map(String::as_str).collect();
+    match (request.method.as_str(), path.as_slice()) {
+        ("PUT", ["user", "create"]) => state.create_user(request),
+        ("PUT", ["user", uuid, "bdo"]) => state.put_bdo(uuid, request),
+        ("GET", ["user", uuid, "bdo"]) => state.get_bdo(uuid, request),
+        ("GET", ["user", uuid, "bases"]) => state.authorized(uuid, request, |state| (200, Some(json!({ "bases": state.bases })))),
+        ("PUT", ["user", uuid, "bases"]) => state.authorized(uuid, request, |state| state.put_bases(request)),
+        ("GET", ["user", uuid, "spellbooks"]) => state.authorized(uuid, request, |state| (200, Some(json!({ "spellbooks": state.spellbooks })))),
+        ("PUT", ["user", uuid, "spellbooks"]) => state.authorized(uuid, request, |state| state.put_spellbook(request)),
+        ("GET", ["user", uuid, "teleport"]) => state.authorized(uuid, request, |state| state.teleport(request)),
+        ("DELETE", ["user", "delete"]) => state.delete_user(request),
+        ("GET", ["emoji", emojicode]) => state.get_by_emojicode(emojicode),
+        ("GET", ["short", short_code]) => state.get_by_short_code(short_code),
+        ("GET", ["pubkey", pub_key, "emojicode"]) => state.get_emojicode(pub_key),
+        ("GET", ["templates", hash]) => state.get_templates(hash),
+        ("POST", ["templates", hash, "add"]) => state.add_template(hash, request),
+        _ => error(404, "no such route"),
+    }
+}
+
+impl State {
+    fn verifies(&self, message: &str, pub_key: &str, signature: &str) -> bool {
+        let (Ok(pub_key), Ok(signature)) = (PublicKey::from_hex(pub_key), Signature::from_hex(signature)) else {
+            return false;
+        };
+
+        self.sessionless.verify(message, &pub_key, &signature).is_ok()
+    }
+
+    /// Continuebee's check: the user signed `timestamp + uuid + hash`.
+    fn is_authorized(&self, uuid: &str, request: &Request) -> bool {
+        let (Some(pub_key), Some(timestamp), Some(hash), Some(signature)) =
+            (self.users.get(uuid), request.param("timestamp"), request.param("hash"), request.param("signature")) else {
+            return false;
+        };
+
+        self.verifies(&format!("{}{}{}", timestamp, uuid, hash), pub_key, &signature)
+    }
+
+    fn authorized(&mut self, uuid: &str, request: &Request, handler: impl FnOnce(&mut Self) -> Reply) -> Reply {
+        if self.is_authorized(uuid, request) {
+            handler(self)
+        } else {
+            auth_error()
+        }
+    }
+
+    /// The pubKey's emojicode, made up the first time it's asked for.
+    fn emojicode_for(&mut self, pub_key: &str) -> String {
+        if let Some((emojicode, _)) = self.emojicodes.iter().find(|(_, (owner, _))| owner == pub_key) {
+            return emojicode.clone();
+        }
+
+        let emojicode = loop {
+            let unique: String = EMOJI_PALETTE.choose_multiple(&mut rand::thread_rng(), 5).copied().collect();
+            let emojicode = format!("{}{}", EMOJICODE_PREFIX, unique);
+            if !self.emojicodes.contains_key(&emojicode) {
+                break emojicode;
+            }
+        };
+        self.emojicodes.insert(emojicode.clone(), (pub_key.to_string(), now_millis()));
+        emojicode
+    }
+
+    /// Saves a BDO the way the node server's `putBDO` does, returning the
+    /// emojicode of a public one.
+    fn save_bdo(&mut self, uuid: &str, hash: &str, bdo: &Value, public_pub_key: Option<&str>) -> Option<String> {
+        self.bdos.insert((uuid.to_string(), hash.to_string()), bdo.clone());
+
+        let pub_key = public_pub_key?;
+        self.public_bdos.insert(pub_key.to_string(), bdo.clone());
+        Some(self.emojicode_for(pub_key))
+    }
+
+    fn create_user(&mut self, request: &Request) -> Reply {
+        let (Some(timestamp), Some(hash), Some(pub_key), Some(signature)) =
+            (request.param("timestamp"), request.param("hash"), request.param("pubKey"), request.param("signature")) else {
+            return auth_error();
+        };
+        if !self.verifies(&format!("{}{}{}", timestamp, pub_key, hash), &pub_key, &signature) {
+            return auth_error();
+        }
+
+        // Continuebee hands a pubKey it already knows its old uuid.
+        let uuid = match self.users.iter().find(|(_, known)| **known == pub_key) {
+            Some((uuid, _)) => uuid.clone(),
+            None => {
+                let uuid = random_uuid();
+                self.users.insert(uuid.clone(), pub_key.clone());
+                uuid
+            },
+        };
+
+        let bdo = request.body.get("bdo").cloned().unwrap_or(Value::Null);
+        let is_public = request.body.get("public").and_then(Value::as_bool).unwrap_or(false);
+        let emoji_shortcode = if bdo.is_null() {
+            None
+        } else {
+            self.save_bdo(&uuid, &hash, &bdo, is_public.then_some(pub_key.as_str()))
+        };
+
+        (200, Some(json!({ "uuid": uuid, "bdo": bdo, "emojiShortcode": emoji_shortcode })))
+    }
+
+    fn put_bdo(&mut self, uuid: &str, request: &Request) -> Reply {
+        if !self.is_authorized(uuid, request) {
+            return auth_error();
+        }
+
+        // Like the real server: a public write is refused, as an auth error,
+        // when the stored BDO says it's public and names a different pubKey.
+        let hash = request.param("hash").unwrap_or_default();
+        let pub_key = request.param("pubKey");
+        let is_public = request.body.get("pub").is_some_and(is_truthy);
+        let existing = self.bdos.get(&(uuid.to_string(), hash.clone()));
+        if is_public && existing.is_some_and(|existing| is_locked_against(existing, pub_key.as_deref())) {
+            return auth_error();
+        }
+
+        // The real server keeps a public copy whenever a pubKey is sent,
+        // whatever `pub` says.
+        let bdo = request.body.get("bdo").cloned().unwrap_or(Value::Null);
+        let emoji_shortcode = self.save_bdo(uuid, &hash, &bdo, pub_key.as_deref());
+
+        (200, Some(json!({ "uuid": uuid, "bdo": bdo, "emojiShortcode": emoji_shortcode })))
+    }
+
+    fn get_bdo(&mut self, uuid: &str, request: &Request) -> Reply {
+        if !self.is_authorized(uuid, request) {
+            return auth_error();
+        }
+
+        let mut pub_key = request.param("pubKey");
+        if let Some(emojicode) = request.param("emojicode") {
+            match self.emojicodes.get(&emojicode) {
+                Some((owner, _)) => pub_key = Some(owner.clone()),
+                None => return error(200, "Emojicode not found"),
+            }
+        }
+
+        let bdo = match pub_key {
+            Some(pub_key) => self.public_bdos.get(&pub_key),
+            None => self.bdos.get(&(uuid.to_string(), request.param("hash").unwrap_or_default())),
+        };
+
+        (200, Some(json!({ "uuid": uuid, "bdo": bdo })))
+    }
+
+    fn put_bases(&mut self, request: &Request) -> Reply {
+        let Some(bases) = request.body.get("bases").and_then(Value::as_object) else {
+            return not_found();
+        };
+
+        self.bases.extend(bases.clone());
+        (200, Some(json!({ "bases": self.bases })))
+    }
+
+    fn put_spellbook(&mut self, request: &Request) -> Reply {
+        let Some(spellbook) = request.body.get("spellbook").filter(|spellbook| spellbook.get("spellbookName").is_some()) else {
+            return not_found();
+        };
+
+        self.spellbooks.push(spellbook.clone());
+        (200, Some(json!({ "spellbooks": self.spellbooks })))
+    }
+
+    fn teleport(&self, request: &Request) -> Reply {
+        let tag = request.param("url")
+            .and_then(|url| self.teleports.get(&url).cloned())
+            .unwrap_or_else(|| json!({ "valid": false }));
+
+        (200, Some(tag))
+    }
+
+    /// The node server only checks the signature; the fake also forgets the
+    /// user and their private BDOs, so later requests show the delete happened.
+    fn delete_user(&mut self, request: &Request) -> Reply {
+        let uuid = request.param("uuid").unwrap_or_default();
+        if !self.is_authorized(&uuid, request) {
+            return auth_error();
+        }
+
+        self.users.remove(&uuid);
+        self.bdos.retain(|(owner, _), _| *owner != uuid);
+        (202, None)
+    }
+
+    fn get_by_emojicode(&self, emojicode: &str) -> Reply {
+        let Some((pub_key, created_at)) = self.emojicodes.get(emojicode) else {
+            return error(404, "Emojicode not found");
+        };
+        let Some(bdo) = self.public_bdos.get(pub_key) else {
+            return error(404, "BDO not found");
+        };
+
+        (200, Some(json!({ "emojicode": emojicode, "pubKey": pub_key, "bdo": bdo, "createdAt": created_at })))
+    }
+
+    fn get_by_short_code(&self, short_code: &str) -> Reply {
+        let Some(pub_key) = self.short_codes.get(short_code) else {
+            return error(404, "Short code not found");
+        };
+        let Some(bdo) = self.public_bdos.get(pub_key) else {
+            return error(404, "BDO not found");
+        };
+
+        (200, Some(json!({ "shortCode": short_code, "pubKey": pub_key, "bdo": bdo })))
+    }
+
+    fn get_emojicode(&self, pub_key: &str) -> Reply {
+        match self.emojicodes.iter().find(|(_, (owner, _))| owner == pub_key) {
+            Some((emojicode, (_, created_at))) => (200, Some(json!({ "pubKey": pub_key, "emojicode": emojicode, "createdAt": created_at }))),
+            None => error(404, "Emojicode not found for this pubKey"),
+        }
+    }
+
+    fn get_templates(&self, hash: &str) -> Reply {
+        let templates: Vec<Value> = self.templates.get(hash).into_iter().flatten()
+            .filter_map(|emojicode| {
+                let (pub_key, _) = self.emojicodes.get(emojicode)?;
+                let mut template = json!({ "emojicode": emojicode, "pubKey": pub_key });
+                if let Some(fields) = self.public_bdos.get(pub_key)?.as_object() {
+                    template.as_object_mut().expect("template object").extend(fields.clone());
+                }
+                Some(template)
+            })
+            .collect();
+
+        (200, Some(json!({ "success": true, "hash": hash, "count": templates.len(), "templates": templates })))
+    }
+
+    fn add_template(&mut self, hash: &str, request: &Request) -> Reply {
+        let Some(emojicode) = request.param("emojicode") else {
+            return error(400, "Missing emojicode");
+        };
+
+        let templates = self.templates.entry(hash.to_string()).or_default();
+        if !templates.contains(&emojicode) {
+            templates.push(emojicode.clone());
+        }
+
+        (200, Some(json!({ "success": true, "hash": hash, "emojicode": emojicode, "message": "Template added to index" })))
+    }
+}
@@ -0,0 +1,244 @@
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sessionless::hex::{FromHex, IntoHex};
+use sessionless::{PrivateKey, Sessionless};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use crate::{BdoError, BDO};
+
+/// The keys and uuid a user has for one app hash.
+#[derive(Clone)]
+pub struct Identity {
+    pub uuid: String,
+    pub private_key: PrivateKey,
+}
+
+impl std::fmt::Debug for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Identity")
+            .field("uuid", &self.uuid)
+            .field("private_key", &crate::trace::REDACTED)
+            .finish()
+    }
+}
+
+/// Somewhere to keep identities between runs, keyed by app hash.
+pub trait KeyStore: Send + Sync {
+    fn load(&self, hash: &str) -> Result<Option<Identity>, BdoError>;
+    fn save(&self, hash: &str, identity: &Identity) -> Result<(), BdoError>;
+    fn remove(&self, hash: &str) -> Result<(), BdoError>;
+}
+
+/// Keeps identities for as long as the process runs. Mostly useful in tests.
+#[derive(Default)]
+pub struct MemoryKeyStore {
+    identities: Mutex<HashMap<String, Identity>>,
+}
+
+impl MemoryKeyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl KeyStore for MemoryKeyStore {
+    fn load(&self, hash: &str) -> Result<Option<Identity>, BdoError> {
+        Ok(self.identities.lock().expect("key store lock").get(hash).cloned())
+    }
+
+    fn save(&self, hash: &str, identity: &Identity) -> Result<(), BdoError> {
+        self.identities.lock().expect("key store lock").insert(hash.to_string(), identity.clone());
+        Ok(())
+    }
+
+    fn remove(&self, hash: &str) -> Result<(), BdoError> {
+        self.identities.lock().expect("key store lock").remove(hash);
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all="camelCase")]
+struct StoredIdentity {
+    uuid: String,
+    private_key: String,
+}
+
+/// The on-disk format: the identity as JSON, encrypted with XChaCha20-Poly1305
+/// under a key derived from the passphrase with Argon2id.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all="camelCase")]
+struct EncryptedFile {
+    version: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+const FILE_VERSION: u32 = 1;
+
+/// Stores each hash's identity in its own passphrase-encrypted file.
+pub struct FileKeyStore {
+    dir: PathBuf,
+    passphrase: String,
+}
+
+impl FileKeyStore {
+    /// Identities are kept in `dir`, which is created on the first save.
+    pub fn new(dir: impl Into<PathBuf>, passphrase: &str) -> Self {
+        FileKeyStore {
+            dir: dir.into(),
+            passphrase: passphrase.to_string(),
+        }
+    }
+
+    /// The file an app hash's identity lives in. Hashes are hex encoded so
+    /// any string makes a safe file name.
+    pub fn path_for(&self, hash: &str) -> PathBuf {
+        self.dir.join(format!("{}.bdokey", hex::encode(hash)))
+    }
+
+    fn cipher(&self, salt: &[u8]) -> Result<XChaCha20Poly1305, BdoError> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(self.passphrase.as_bytes(), salt, &mut key)
+            .map_err(|err| BdoError::KeyStore(format!("could not derive key: {}", err)))?;
+
+        Ok(XChaCha20Poly1305::new(Key::from_slice(&key)))
+    }
+}
+
+fn decode_hex(field: &str, value: &str) -> Result<Vec<u8>, BdoError> {
+    hex::decode(value).map_err(|_| BdoError::KeyStore(format!("corrupt key file: bad {}", field)))
+}
+
+/// Writes `contents` through a temporary file that's only ever readable by
+/// its owner: it's created fresh with mode 0600, so there's no moment when
+/// it has the umask's permissions.
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    match fs::remove_file(&tmp) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+        _ => {},
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp, path)
+}
+
+impl KeyStore for FileKeyStore {
+    fn load(&self, hash: &str) -> Result<Option<Identity>, BdoError> {
+        let contents = match fs::read(self.path_for(hash)) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(BdoError::KeyStore(format!("could not read key file: {}", err))),
+        };
+
+        let file: EncryptedFile = serde_json::from_slice(&contents)
+            .map_err(|err| BdoError::KeyStore(format!("corrupt key file: {}", err)))?;
+        if file.version != FILE_VERSION {
+            return Err(BdoError::KeyStore(format!("unsupported key file version {}", file.version)));
+        }
+
+        let salt = decode_hex("salt", &file.salt)?;
+        let nonce = decode_hex("nonce", &file.nonce)?;
+        let ciphertext = decode_hex("ciphertext", &file.ciphertext)?;
+        if nonce.len() != 24 {
+            return Err(BdoError::KeyStore("corrupt key file: bad nonce".to_string()));
+        }
+
+        let plaintext = self.cipher(&salt)?
+            .decrypt(XNonce::from_slice(&nonce), ciphertext.as_ref())
+            .map_err(|_| BdoError::KeyStore("wrong passphrase or tampered key file".to_string()))?;
+        let stored: StoredIdentity = serde_json::from_slice(&plaintext)
+            .map_err(|err| BdoError::KeyStore(format!("corrupt key file: {}", err)))?;
+        let private_key = PrivateKey::from_hex(&stored.private_key)
+            .map_err(|err| BdoError::KeyStore(format!("corrupt private key: {}", err)))?;
+
+        Ok(Some(Identity { uuid: stored.uuid, private_key }))
+    }
+
+    fn save(&self, hash: &str, identity: &Identity) -> Result<(), BdoError> {
+        let mut salt = [0u8; 16];
+        let mut nonce = [0u8; 24];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let plaintext = serde_json::to_vec(&StoredIdentity {
+            uuid: identity.uuid.clone(),
+            private_key: identity.private_key.to_hex(),
+        }).map_err(|err| BdoError::KeyStore(format!("could not serialize identity: {}", err)))?;
+        let ciphertext = self.cipher(&salt)?
+            .encrypt(XNonce::from_slice(&nonce), plaintext.as_ref())
+            .map_err(|_| BdoError::KeyStore("could not encrypt identity".to_string()))?;
+
+        let file = EncryptedFile {
+            version: FILE_VERSION,
+            salt: hex::encode(salt),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(&ciphertext),
+        };
+        let contents = serde_json::to_vec_pretty(&file)
+            .map_err(|err| BdoError::KeyStore(format!("could not serialize key file: {}", err)))?;
+
+        fs::create_dir_all(&self.dir)
+            .and_then(|_| write_private(&self.path_for(hash), &contents))
+            .map_err(|err| BdoError::KeyStore(format!("could not write key file: {}", err)))
+    }
+
+    fn remove(&self, hash: &str) -> Result<(), BdoError> {
+        match fs::remove_file(self.path_for(hash)) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(BdoError::KeyStore(format!("could not remove key file: {}", err))),
+        }
+    }
+}
+
+impl BDO {
+    /// The Sessionless `checkForUser()`: if `store` has an identity for
+    /// `hash`, switches this client to its keys and returns its uuid.
+    pub fn check_for_user(&mut self, store: &dyn KeyStore, hash: &str) -> Result<Option<String>, BdoError> {
+        match store.load(hash)? {
+            Some(identity) => {
+                self.sessionless = Sessionless::from_private_key(identity.private_key);
+                Ok(Some(identity.uuid))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Reloads the identity for `hash`, or creates a user with this client's
+    /// keys and saves it. Returns the user's uuid either way.
+    pub async fn ensure_user(&mut self, store: &dyn KeyStore, hash: &str, bdo: &Value, is_public: &bool) -> Result<String, BdoError> {
+        if let Some(uuid) = self.check_for_user(store, hash)? {
+            return Ok(uuid);
+        }
+
+        let user = self.create_user(hash, bdo, is_public).await?;
+        store.save(hash, &Identity {
+            uuid: user.uuid.clone(),
+            private_key: *self.sessionless.private_key(),
+        })?;
+
+        Ok(user.uuid)
+    }
+}
@@ -1,31 +1,77 @@
+pub mod allyabase;
+pub mod api;
+pub mod bases;
+pub mod blocking;
+pub mod builder;
+pub mod cache;
+pub mod canonical;
+pub mod cas;
+pub mod envelope;
+pub mod error;
+#[cfg(any(test, feature = "fake-server"))]
+pub mod fake;
+pub mod keystore;
+pub mod magic;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod patch;
+pub mod retry;
+pub mod session;
+pub mod spellbook;
 pub mod structs;
+pub mod teleport;
+pub mod trace;
+pub mod typed;
 
 #[cfg(test)]
 mod tests;
 
+use indexmap::IndexMap;
 use reqwest::{Client, Response};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use serde_json::Value;
+use serde_json::{Map, Value};
 use sessionless::hex::IntoHex;
-use sessionless::{Sessionless, Signature};
+use sessionless::Sessionless;
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::collections::HashMap;
 use std::option::Option;
-use crate::structs::{BDOUser, SuccessResult, EmojicodeResponse};
-
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all="camelCase")]
+use std::sync::Arc;
+pub use crate::allyabase::AllyabaseUrl;
+pub use crate::api::BdoApi;
+pub use crate::bases::BaseConfig;
+pub use crate::blocking::BlockingBDO;
+pub use crate::builder::BdoBuilder;
+pub use crate::cache::{CachePolicy, Cached, FileCacheStore, Freshness, MemoryCacheStore};
+pub use crate::cas::BdoConflict;
+pub use crate::error::{BdoError, ErrorBody};
+pub use crate::keystore::{FileKeyStore, Identity, KeyStore, MemoryKeyStore};
+pub use crate::magic::{Caster, Spell};
+pub use crate::retry::RetryPolicy;
+pub use crate::session::BdoSession;
+use crate::cache::BdoCache;
+use crate::canonical::WrittenBdos;
+use crate::retry::Operation;
+use crate::spellbook::Spell as SpellDefinition;
+use crate::structs::{AddTemplateResponse, BDOUser, SuccessResult, EmojicodeResponse, PubKeyEmojicodeResponse, ShortCodeResponse, TeleportResponse, Template, Templates};
+
+/// Serializes as one flat object: `spellbookName`, then the spells, then
+/// any other keys.
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Spellbook {
     pub spellbookName: String,
-    #[serde(flatten)]
-    spells: serde_json::Value
+    /// Every other key of the spellbook whose value is a spell, kept in the order the server sent them.
+    pub spells: IndexMap<String, SpellDefinition>,
+    /// Keys whose values aren't spells, kept so they survive a round trip.
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A user's bases, keyed by base id.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all="camelCase")]
 pub struct Bases {
-    pub bases: serde_json::Value
+    pub bases: IndexMap<String, BaseConfig>
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -38,25 +84,67 @@ pub struct BDO {
     base_url: String,
     client: Client,
     pub sessionless: Sessionless,
+    caster: Option<Caster>,
+    resolver_url: Option<String>,
+    retry_policy: RetryPolicy,
+    signed_public_bdos: bool,
+    skip_unchanged_writes: bool,
+    teleport_content_hashes: bool,
+    written_bdos: WrittenBdos,
+    cas_attempts: u32,
+    cache: Option<Arc<BdoCache>>,
 }
 
 impl BDO {
+    /// `base_url` may be an `allyabase://bdo` URL, which is translated with the
+    /// default service table. Use the builder to change the table.
+    ///
+    /// An `allyabase://` URL that doesn't parse or names an unknown service
+    /// can't be reported from here, so it's kept as it is and logged as a
+    /// warning, and every request will fail. `BDO::builder()` returns the
+    /// error instead.
     pub fn new(base_url: Option<String>, sessionless: Option<Sessionless>) -> Self {
-        println!("🏗️ BDO::new() called with base_url: {:?}", base_url);
-        let final_base_url = base_url.unwrap_or("https://dev.bdo.allyabase.com/".to_string());
-        println!("🏗️ BDO using final base_url: {}", final_base_url);
+        let mut final_base_url = base_url.unwrap_or(builder::DEFAULT_BASE_URL.to_string());
+        match allyabase::translate_url(&final_base_url, &allyabase::ServiceTable::default()) {
+            Ok(translated) => final_base_url = translated,
+            Err(err) => trace::untranslated_url(&final_base_url, &err),
+        }
+        if !final_base_url.ends_with('/') {
+            final_base_url.push('/');
+        }
         BDO {
             base_url: final_base_url,
             client: Client::new(),
             sessionless: sessionless.unwrap_or(Sessionless::new()),
+            caster: None,
+            resolver_url: None,
+            retry_policy: RetryPolicy::default(),
+            signed_public_bdos: false,
+            skip_unchanged_writes: false,
+            teleport_content_hashes: false,
+            written_bdos: WrittenBdos::default(),
+            cas_attempts: cas::DEFAULT_CAS_ATTEMPTS,
+            cache: None,
         }
     }
 
+    /// Configures a client with timeouts, a custom `reqwest::Client`, a path
+    /// prefix and so on. Unlike `new`, the base URL is validated.
+    pub fn builder() -> BdoBuilder {
+        BdoBuilder::new()
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
     async fn get(&self, url: &str) -> Result<Response, reqwest::Error> {
+        trace::request("GET", url, None);
         self.client.get(url).send().await
     }
 
     async fn post(&self, url: &str, payload: serde_json::Value) -> Result<Response, reqwest::Error> {
+        trace::request("POST", url, Some(&payload));
         self.client
             .post(url)
             .json(&payload)
@@ -65,6 +153,7 @@ impl BDO {
     }
 
     async fn put(&self, url: &str, payload: serde_json::Value) -> Result<Response, reqwest::Error> {
+        trace::request("PUT", url, Some(&payload));
         self.client
             .put(url)
             .json(&payload)
@@ -73,6 +162,7 @@ impl BDO {
     }
 
     async fn delete(&self, url: &str, payload: serde_json::Value) -> Result<Response, reqwest::Error> {
+        trace::request("DELETE", url, Some(&payload));
         self.client
             .delete(url)
             .json(&payload)
@@ -80,6 +170,27 @@ impl BDO {
             .await
     }
 
+    async fn parse<T: DeserializeOwned>(res: Response) -> Result<T, BdoError> {
+        let status = res.status();
+        let body = res.text().await?;
+
+        Self::parse_body(status, body)
+    }
+
+    fn parse_body<T: DeserializeOwned>(status: reqwest::StatusCode, body: String) -> Result<T, BdoError> {
+        trace::response(status, &body);
+        if let Some(err) = BdoError::from_response(status, &body) {
+            trace::failure(&err);
+            return Err(err);
+        }
+
+        serde_json::from_str(&body).map_err(|source| {
+            let err = BdoError::Decode { source, body };
+            trace::failure(&err);
+            err
+        })
+    }
+
     fn get_timestamp() -> String {
         SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -88,197 +199,375 @@ impl BDO {
             .to_string()
     }
 
-    pub async fn create_user(&self, hash: &str, bdo: &Value, is_public: &bool) -> Result<BDOUser, Box<dyn std::error::Error>> {
-        let timestamp = Self::get_timestamp();
-        let pub_key = self.sessionless.public_key().to_hex();
-        let signature = self.sessionless.sign(&format!("{}{}{}", timestamp, pub_key, hash)).to_hex();
-        
-        let payload = json!({
-            "timestamp": timestamp,
-            "pubKey": pub_key,
-            "hash": hash,
-            "bdo": bdo,
-            "public": is_public,
-            "signature": signature
-        }).as_object().unwrap().clone();
-
-dbg!("{}", payload.clone());
-
-        println!("🔧 BDO client base_url: {}", self.base_url);
-        let url = format!("{}user/create", self.base_url);
-        println!("🔗 BDO final URL: {}", &url);
-dbg!("{}", &url);
-        let res = self.put(&url, serde_json::Value::Object(payload)).await?;
-dbg!("{}", &res);
-        let user: BDOUser = res.json().await?;
-
-        Ok(user)
-    }
-
-    pub async fn update_bdo(&self, uuid: &str, hash: &str, bdo: &Value, is_public: &bool) -> Result<BDOUser, Box<dyn std::error::Error>> {
-        let timestamp = Self::get_timestamp();
-        let message = format!("{}{}{}", timestamp, uuid, hash);
-        let signature = self.sessionless.sign(message).to_hex();
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub async fn create_user(&self, hash: &str, bdo: &Value, is_public: &bool) -> Result<BDOUser, BdoError> {
+        let fingerprint = self.write_fingerprint(bdo, is_public);
+        let signed = self.sign_if_public(bdo, is_public)?;
+        let bdo = signed.as_ref().unwrap_or(bdo);
+        let user = if self.resolver_url.is_some() {
+            self.cast_user_create(hash, bdo, is_public).await.map(BDOUser::from)?
+        } else {
+            self.create_user_request(hash, bdo, is_public).await?
+        };
+        self.remember_write(&user.uuid, hash, fingerprint);
+        if *is_public {
+            self.forget_cached(&[cache::public_bdo_key(&self.sessionless.public_key().to_hex())]);
+        }
 
-        let payload = json!({
-            "timestamp": timestamp,
-            "uuid": uuid,
-            "hash": hash,
-            "pub": is_public,
-            "pubKey": self.sessionless.public_key().to_hex(),
-            "bdo": bdo,
-            "signature": signature
-        }).as_object().unwrap().clone();
+        Ok(self.strip_envelope(user))
+    }
+
+    async fn create_user_request(&self, hash: &str, bdo: &Value, is_public: &bool) -> Result<BDOUser, BdoError> {
+        self.retrying(Operation::Write, move || async move {
+            let timestamp = Self::get_timestamp();
+            let pub_key = self.sessionless.public_key().to_hex();
+            let signature = self.sessionless.sign(format!("{}{}{}", timestamp, pub_key, hash)).to_hex();
+
+            let payload = json!({
+                "timestamp": timestamp,
+                "pubKey": pub_key,
+                "hash": hash,
+                "bdo": bdo,
+                "public": is_public,
+                "signature": signature
+            }).as_object().unwrap().clone();
+
+            let url = format!("{}user/create", self.base_url);
+            let res = self.put(&url, serde_json::Value::Object(payload)).await?;
+            let user: BDOUser = Self::parse(res).await?;
+
+            Ok(user)
+        }).await
+    }
 
-        let url = format!("{}user/{}/bdo", self.base_url, uuid);
-        let res = self.put(&url, serde_json::Value::Object(payload)).await?;
-        let user: BDOUser = res.json().await?;
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(uuid = %uuid)))]
+    pub async fn update_bdo(&self, uuid: &str, hash: &str, bdo: &Value, is_public: &bool) -> Result<BDOUser, BdoError> {
+        let fingerprint = self.write_fingerprint(bdo, is_public);
+        if self.is_unchanged(uuid, hash, fingerprint.as_deref()) {
+            return Ok(BDOUser { uuid: uuid.to_string(), bdo: bdo.clone() });
+        }
 
-        Ok(user)
+        let signed = self.sign_if_public(bdo, is_public)?;
+        let bdo = signed.as_ref().unwrap_or(bdo);
+        let written = if self.resolver_url.is_some() {
+            self.cast_user_bdo(uuid, hash, bdo, is_public).await.map(BDOUser::from)
+        } else {
+            match self.update_bdo_request(uuid, hash, bdo, is_public).await {
+                Err(BdoError::Auth { status, message }) if *is_public => Err(self.explain_refused_write(uuid, hash, status, message).await),
+                written => written,
+            }
+        };
+        // A failed write may still have landed, so the cache goes either way.
+        self.forget_cached(&[cache::bdo_key(uuid, hash), cache::public_bdo_key(&self.sessionless.public_key().to_hex())]);
+        let user = written?;
+        self.remember_write(uuid, hash, fingerprint);
+
+        Ok(self.strip_envelope(user))
     }
 
-    pub async fn get_bdo(&self, uuid: &str, hash: &str) -> Result<BDOUser, Box<dyn std::error::Error>> {
-        let timestamp = Self::get_timestamp();
-        let message = format!("{}{}{}", timestamp, uuid, hash);
-        let signature = self.sessionless.sign(message).to_hex();
+    async fn update_bdo_request(&self, uuid: &str, hash: &str, bdo: &Value, is_public: &bool) -> Result<BDOUser, BdoError> {
+        self.retrying(Operation::Write, move || async move {
+            let timestamp = Self::get_timestamp();
+            let message = format!("{}{}{}", timestamp, uuid, hash);
+            let signature = self.sessionless.sign(message).to_hex();
+
+            let payload = json!({
+                "timestamp": timestamp,
+                "uuid": uuid,
+                "hash": hash,
+                "pub": is_public,
+                "pubKey": self.sessionless.public_key().to_hex(),
+                "bdo": bdo,
+                "signature": signature
+            }).as_object().unwrap().clone();
+
+            let url = format!("{}user/{}/bdo", self.base_url, uuid);
+            let res = self.put(&url, serde_json::Value::Object(payload)).await?;
+            let user: BDOUser = Self::parse(res).await?;
+
+            Ok(user)
+        }).await
+    }
 
-        let url = format!("{}user/{}/bdo?timestamp={}&hash={}&signature={}", self.base_url, uuid, timestamp, hash, signature);
-        let res = self.get(&url).await?;
-        let user: BDOUser = res.json().await?;
- 
-        Ok(user)
+    /// The REST route refuses a public write over a BDO locked to another
+    /// pubKey with a plain `Auth error`. If the stored BDO can still be read
+    /// and is locked to someone else, that's what happened.
+    async fn explain_refused_write(&self, uuid: &str, hash: &str, status: reqwest::StatusCode, message: String) -> BdoError {
+        let pub_key = self.sessionless.public_key().to_hex();
+        match self.uncached_read::<BDOUser, _>(|| self.bdo_url(uuid, hash)).await {
+            Ok(stored) if error::is_locked_against(&stored.bdo, Some(&pub_key)) => BdoError::PubKeyConflict {
+                status,
+                message: "the public BDO is locked to a different pubKey".to_string(),
+            },
+            _ => BdoError::Auth { status, message },
+        }
     }
 
-    pub async fn get_public_bdo(&self, uuid: &str, hash: &str, pub_key: &str) -> Result<BDOUser, Box<dyn std::error::Error>> {
-        let timestamp = Self::get_timestamp();
-        let message = format!("{}{}{}", timestamp, uuid, hash);
-        let signature = self.sessionless.sign(message).to_hex();
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(uuid = %uuid)))]
+    pub async fn get_bdo(&self, uuid: &str, hash: &str) -> Result<BDOUser, BdoError> {
+        self.get_bdo_cached(uuid, hash).await.map(Cached::into_value)
+    }
 
-        let url = format!("{}user/{}/bdo?timestamp={}&hash={}&signature={}&pubKey={}", self.base_url, uuid, timestamp, hash, signature, pub_key);
-dbg!("{}", &url);
-dbg!("{}", &self.sessionless.public_key().to_hex());
-        let res = self.get(&url).await?;
-        let user: BDOUser = res.json().await?;
- 
-        Ok(user)
+    /// `get_bdo`, and whether the BDO came from the cache.
+    pub async fn get_bdo_cached(&self, uuid: &str, hash: &str) -> Result<Cached<BDOUser>, BdoError> {
+        self.cached_read(cache::bdo_key(uuid, hash), || self.bdo_url(uuid, hash)).await
     }
 
-    pub async fn get_bases(&self, uuid: &str, hash: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    /// A freshly signed `GET /user/:uuid/bdo` URL.
+    pub(crate) fn bdo_url(&self, uuid: &str, hash: &str) -> String {
         let timestamp = Self::get_timestamp();
         let message = format!("{}{}{}", timestamp, uuid, hash);
         let signature = self.sessionless.sign(message).to_hex();
 
-        let url = format!("{}user/{}/bases?timestamp={}&hash={}&signature={}", self.base_url, uuid, timestamp, hash, signature);
-        let res = self.get(&url).await?;
-        let bases: Bases = res.json().await?;
- 
-        Ok(bases.bases)
+        format!("{}user/{}/bdo?timestamp={}&hash={}&signature={}", self.base_url, uuid, timestamp, hash, signature)
     }
 
-    pub async fn save_bases(&self, uuid: &str, hash: &str, bases: &Bases) -> Result<Value, Box<dyn std::error::Error>> {
-        let timestamp = Self::get_timestamp();
-        let message = format!("{}{}{}", timestamp, uuid, hash);
-        let signature = self.sessionless.sign(message).to_hex();
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(uuid = %uuid)))]
+    pub async fn get_public_bdo(&self, uuid: &str, hash: &str, pub_key: &str) -> Result<BDOUser, BdoError> {
+        self.get_public_bdo_cached(uuid, hash, pub_key).await.map(Cached::into_value)
+    }
+
+    /// `get_public_bdo`, and whether the BDO came from the cache. Public BDOs
+    /// are cached by pubKey alone, and verified on every read.
+    pub async fn get_public_bdo_cached(&self, uuid: &str, hash: &str, pub_key: &str) -> Result<Cached<BDOUser>, BdoError> {
+        self.cached_read(cache::public_bdo_key(pub_key), || {
+            let timestamp = Self::get_timestamp();
+            let message = format!("{}{}{}", timestamp, uuid, hash);
+            let signature = self.sessionless.sign(message).to_hex();
+
+            format!("{}user/{}/bdo?timestamp={}&hash={}&signature={}&pubKey={}", self.base_url, uuid, timestamp, hash, signature, pub_key)
+        }).await.and_then(|mut cached: Cached<BDOUser>| {
+            cached.value.uuid = uuid.to_string();
+            cached.value.bdo = self.verify_if_signing(cached.value.bdo, pub_key)?;
+            Ok(cached)
+        })
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(uuid = %uuid)))]
+    pub async fn get_bases(&self, uuid: &str, hash: &str) -> Result<Bases, BdoError> {
+        self.get_bases_cached(uuid, hash).await.map(Cached::into_value)
+    }
 
-        let payload = json!({
-            "timestamp": timestamp,
-            "uuid": uuid,
-            "hash": hash,
-            "bases": bases,
-            "signature": signature
-        }).as_object().unwrap().clone();
+    /// `get_bases`, and whether the bases came from the cache.
+    pub async fn get_bases_cached(&self, uuid: &str, hash: &str) -> Result<Cached<Bases>, BdoError> {
+        self.cached_read(cache::bases_key(uuid, hash), || {
+            let timestamp = Self::get_timestamp();
+            let message = format!("{}{}{}", timestamp, uuid, hash);
+            let signature = self.sessionless.sign(message).to_hex();
 
-        let url = format!("{}user/{}/bases", self.base_url, uuid);
-        let res = self.put(&url, serde_json::Value::Object(payload)).await?;
-        let bases: Bases = res.json().await?;
+            format!("{}user/{}/bases?timestamp={}&hash={}&signature={}", self.base_url, uuid, timestamp, hash, signature)
+        }).await
+    }
 
-        Ok(bases.bases)
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(uuid = %uuid)))]
+    pub async fn save_bases(&self, uuid: &str, hash: &str, bases: &Bases) -> Result<Bases, BdoError> {
+        let saved = self.save_bases_request(uuid, hash, bases).await;
+        self.forget_cached(&[cache::bases_key(uuid, hash)]);
+        saved
     }
 
+    async fn save_bases_request(&self, uuid: &str, hash: &str, bases: &Bases) -> Result<Bases, BdoError> {
+        if self.resolver_url.is_some() {
+            return self.cast_user_bases(uuid, hash, bases).await.map(|spell_bases| Bases { bases: spell_bases.bases });
+        }
+
+        self.retrying(Operation::Write, move || async move {
+            let timestamp = Self::get_timestamp();
+            let message = format!("{}{}{}", timestamp, uuid, hash);
+            let signature = self.sessionless.sign(message).to_hex();
+
+            let payload = json!({
+                "timestamp": timestamp,
+                "uuid": uuid,
+                "hash": hash,
+                "bases": bases.bases,
+                "signature": signature
+            }).as_object().unwrap().clone();
+
+            let url = format!("{}user/{}/bases", self.base_url, uuid);
+            let res = self.put(&url, serde_json::Value::Object(payload)).await?;
+            let bases: Bases = Self::parse(res).await?;
+
+            Ok(bases)
+        }).await
+    }
 
 
-    pub async fn get_spellbooks(&self, uuid: &str, hash: &str) -> Result<Vec<Spellbook>, Box<dyn std::error::Error>> {
-        let timestamp = Self::get_timestamp();
-        let message = format!("{}{}{}", timestamp, uuid, hash);
-        let signature = self.sessionless.sign(message).to_hex();
 
-        let url = format!("{}user/{}/spellbooks?timestamp={}&hash={}&signature={}", self.base_url, uuid, timestamp, hash, signature);
-        let res = self.get(&url).await?;
-        let spellbooks: Spellbooks = res.json().await?;
- 
-        Ok(spellbooks.spellbooks)
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(uuid = %uuid)))]
+    pub async fn get_spellbooks(&self, uuid: &str, hash: &str) -> Result<Vec<Spellbook>, BdoError> {
+        self.get_spellbooks_cached(uuid, hash).await.map(Cached::into_value)
     }
 
-    pub async fn put_spellbook(&self, uuid: &str, hash: &str, spellbook: &Spellbook) -> Result<Vec<Spellbook>, Box<dyn std::error::Error>> {
-        let timestamp = Self::get_timestamp();
-        let message = format!("{}{}{}", timestamp, uuid, hash);
-        let signature = self.sessionless.sign(message).to_hex();
+    /// `get_spellbooks`, and whether the spellbooks came from the cache.
+    pub async fn get_spellbooks_cached(&self, uuid: &str, hash: &str) -> Result<Cached<Vec<Spellbook>>, BdoError> {
+        self.cached_read(cache::spellbooks_key(uuid, hash), || {
+            let timestamp = Self::get_timestamp();
+            let message = format!("{}{}{}", timestamp, uuid, hash);
+            let signature = self.sessionless.sign(message).to_hex();
 
-        let payload = json!({
-            "timestamp": timestamp,
-            "uuid": uuid,
-            "hash": hash,
-            "spellbook": spellbook,
-            "signature": signature
-        }).as_object().unwrap().clone();
+            format!("{}user/{}/spellbooks?timestamp={}&hash={}&signature={}", self.base_url, uuid, timestamp, hash, signature)
+        }).await.map(|cached: Cached<Spellbooks>| cached.map(|spellbooks| spellbooks.spellbooks))
+    }
 
-        let url = format!("{}user/{}/spellbooks", self.base_url, uuid);
-        let res = self.put(&url, serde_json::Value::Object(payload)).await?;
-        let spellbooks: Vec<Spellbook> = res.json().await?;
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(uuid = %uuid)))]
+    pub async fn put_spellbook(&self, uuid: &str, hash: &str, spellbook: &Spellbook) -> Result<Vec<Spellbook>, BdoError> {
+        let saved = self.put_spellbook_request(uuid, hash, spellbook).await;
+        self.forget_cached(&[cache::spellbooks_key(uuid, hash)]);
+        saved
+    }
+
+    async fn put_spellbook_request(&self, uuid: &str, hash: &str, spellbook: &Spellbook) -> Result<Vec<Spellbook>, BdoError> {
+        if self.resolver_url.is_some() {
+            return self.cast_user_spellbooks(uuid, hash, spellbook).await.map(|spell_spellbooks| spell_spellbooks.spellbooks);
+        }
 
-        Ok(spellbooks)
+        self.retrying(Operation::Write, move || async move {
+            let timestamp = Self::get_timestamp();
+            let message = format!("{}{}{}", timestamp, uuid, hash);
+            let signature = self.sessionless.sign(message).to_hex();
+
+            let payload = json!({
+                "timestamp": timestamp,
+                "uuid": uuid,
+                "hash": hash,
+                "spellbook": spellbook,
+                "signature": signature
+            }).as_object().unwrap().clone();
+
+            let url = format!("{}user/{}/spellbooks", self.base_url, uuid);
+            let res = self.put(&url, serde_json::Value::Object(payload)).await?;
+            let spellbooks: Spellbooks = Self::parse(res).await?;
+
+            Ok(spellbooks.spellbooks)
+        }).await
     }
 
-    pub async fn delete_user(&self, uuid: &str, hash: &str) -> Result<SuccessResult, Box<dyn std::error::Error>> {
-        let timestamp = Self::get_timestamp();
-        let message = format!("{}{}", timestamp, uuid);
-        let signature = self.sessionless.sign(&message).to_hex();
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(uuid = %uuid)))]
+    pub async fn delete_user(&self, uuid: &str, hash: &str) -> Result<SuccessResult, BdoError> {
+        self.forget_writes(uuid);
+        self.invalidate_cache(uuid, hash);
+        self.retrying(Operation::Write, move || async move {
+            let timestamp = Self::get_timestamp();
+            let message = format!("{}{}{}", timestamp, uuid, hash);
+            let signature = self.sessionless.sign(message).to_hex();
+
+            let payload = json!({
+              "timestamp": timestamp,
+              "uuid": uuid,
+              "hash": hash,
+              "signature": signature
+            }).as_object().unwrap().clone();
+
+            let url = format!("{}user/delete", self.base_url);
+            let res = self.delete(&url, serde_json::Value::Object(payload)).await?;
+            let status = res.status();
+            let body = res.text().await?;
+
+            // A successful delete answers 202 with an empty body.
+            if status.is_success() && body.trim().is_empty() {
+                return Ok(SuccessResult { success: true });
+            }
+            let success: SuccessResult = Self::parse_body(status, body)?;
+
+            Ok(success)
+        }).await
+    }
+
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(uuid = %uuid)))]
+    pub async fn teleport(&self, uuid: &str, hash: &str, url: &str) -> Result<TeleportResponse, BdoError> {
+        self.retrying(Operation::Read, move || async move {
+            let timestamp = Self::get_timestamp();
+            let message = format!("{}{}{}", timestamp, uuid, hash);
+            let signature = self.sessionless.sign(message).to_hex();
+
+            // Don't translate here - let the BDO server handle allyabase:// protocol
+            let teleport_url = format!(
+                "{}user/{}/teleport?timestamp={}&hash={}&signature={}&url={}",
+                self.base_url,
+                uuid,
+                timestamp,
+                hash,
+                signature,
+                urlencoding::encode(url)
+            );
+
+            let res = self.get(&teleport_url).await?;
+            let teleported_content: TeleportResponse = Self::parse(res).await?;
+
+            Ok(teleported_content)
+        }).await
+    }
 
-        let payload = json!({
-          "timestamp": timestamp,
-          "uuid": uuid,
-          "hash": hash,
-          "signature": signature
-        }).as_object().unwrap().clone();
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub async fn get_bdo_by_emojicode(&self, emojicode: &str) -> Result<EmojicodeResponse, BdoError> {
+        self.retrying(Operation::Read, move || async move {
+            let encoded_emojicode = urlencoding::encode(emojicode);
+            let url = format!("{}emoji/{}", self.base_url, encoded_emojicode);
 
-        let url = format!("{}user/{}/delete", self.base_url, uuid);
-        let res = self.delete(&url, serde_json::Value::Object(payload)).await?;
-        let success: SuccessResult = res.json().await?;
+            let res = self.get(&url).await?;
+            let emojicode_response: EmojicodeResponse = Self::parse(res).await?;
 
-        Ok(success)
+            Ok(emojicode_response)
+        }).await.and_then(|mut emojicode_response| {
+            emojicode_response.bdo = self.verify_if_signing(emojicode_response.bdo.take(), &emojicode_response.pub_key)?;
+            Ok(emojicode_response)
+        })
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub async fn get_bdo_by_short_code(&self, short_code: &str) -> Result<ShortCodeResponse, BdoError> {
+        self.retrying(Operation::Read, move || async move {
+            let encoded_short_code = urlencoding::encode(short_code);
+            let url = format!("{}short/{}", self.base_url, encoded_short_code);
 
-    pub async fn teleport(&self, uuid: &str, hash: &str, url: &str) -> Result<Value, Box<dyn std::error::Error>> {
-        let timestamp = Self::get_timestamp();
-        let message = format!("{}{}{}", timestamp, uuid, hash);
-        let signature = self.sessionless.sign(&message).to_hex();
+            let res = self.get(&url).await?;
+            let short_code_response: ShortCodeResponse = Self::parse(res).await?;
+
+            Ok(short_code_response)
+        }).await.and_then(|mut short_code_response| {
+            short_code_response.bdo = self.verify_if_signing(short_code_response.bdo.take(), &short_code_response.pub_key)?;
+            Ok(short_code_response)
+        })
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub async fn get_emojicode_for_pub_key(&self, pub_key: &str) -> Result<PubKeyEmojicodeResponse, BdoError> {
+        self.retrying(Operation::Read, move || async move {
+            let url = format!("{}pubkey/{}/emojicode", self.base_url, urlencoding::encode(pub_key));
+
+            let res = self.get(&url).await?;
+            let pub_key_emojicode: PubKeyEmojicodeResponse = Self::parse(res).await?;
+
+            Ok(pub_key_emojicode)
+        }).await
+    }
 
-        // Don't translate here - let the BDO server handle allyabase:// protocol
-        let teleport_url = format!(
-            "{}user/{}/teleport?timestamp={}&hash={}&signature={}&url={}",
-            self.base_url,
-            uuid,
-            timestamp,
-            hash,
-            signature,
-            urlencoding::encode(url)
-        );
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub async fn get_templates(&self, hash: &str) -> Result<Vec<Template>, BdoError> {
+        self.retrying(Operation::Read, move || async move {
+            let url = format!("{}templates/{}", self.base_url, urlencoding::encode(hash));
 
-        dbg!(&teleport_url);
-        let res = self.get(&teleport_url).await?;
-        let teleported_content: Value = res.json().await?;
+            let res = self.get(&url).await?;
+            let templates: Templates = Self::parse(res).await?;
 
-        Ok(teleported_content)
+            Ok(templates.templates)
+        }).await
     }
 
-    pub async fn get_bdo_by_emojicode(&self, emojicode: &str) -> Result<EmojicodeResponse, Box<dyn std::error::Error>> {
-        let encoded_emojicode = urlencoding::encode(emojicode);
-        let url = format!("{}emoji/{}", self.base_url, encoded_emojicode);
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub async fn add_template(&self, hash: &str, emojicode: &str) -> Result<AddTemplateResponse, BdoError> {
+        self.retrying(Operation::Write, move || async move {
+            let payload = json!({
+                "emojicode": emojicode
+            });
 
-        let res = self.get(&url).await?;
-        let emojicode_response: EmojicodeResponse = res.json().await?;
+            let url = format!("{}templates/{}/add", self.base_url, urlencoding::encode(hash));
+            let res = self.post(&url, payload).await?;
+            let added: AddTemplateResponse = Self::parse(res).await?;
 
-        Ok(emojicode_response)
+            Ok(added)
+        }).await
     }
 }
@@ -0,0 +1,203 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_json::Value;
+use sessionless::hex::IntoHex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::structs::{SpellBases, SpellSpellbooks, SpellUser};
+use crate::retry::Operation;
+use crate::{BdoError, Bases, Spellbook, BDO};
+
+/// Every bdoUser* spell costs the same amount of MP.
+pub const SPELL_COST: u64 = 50;
+
+pub const BDO_USER_CREATE: &str = "bdoUserCreate";
+pub const BDO_USER_BDO: &str = "bdoUserBdo";
+pub const BDO_USER_BASES: &str = "bdoUserBases";
+pub const BDO_USER_SPELLBOOKS: &str = "bdoUserSpellbooks";
+
+/// The fount user spells are cast as.
+///
+/// Fount rejects replayed spells, so the ordinal goes up by one for every
+/// spell cast. Start it wherever the caster's last spell left off.
+#[derive(Debug)]
+pub struct Caster {
+    pub uuid: String,
+    ordinal: AtomicU64,
+}
+
+impl Caster {
+    pub fn new(uuid: &str, ordinal: u64) -> Self {
+        Caster {
+            uuid: uuid.to_string(),
+            ordinal: AtomicU64::new(ordinal),
+        }
+    }
+
+    /// The ordinal the next spell will be cast with.
+    pub fn ordinal(&self) -> u64 {
+        self.ordinal.load(Ordering::SeqCst)
+    }
+
+    fn next_ordinal(&self) -> u64 {
+        self.ordinal.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct Spell {
+    pub spell: String,
+    #[serde(rename="casterUUID")]
+    pub caster_uuid: String,
+    pub timestamp: String,
+    pub total_cost: u64,
+    pub mp: bool,
+    pub ordinal: u64,
+    pub components: Value,
+    pub caster_signature: String,
+}
+
+impl Spell {
+    /// The message the caster signs: timestamp + spell + casterUUID + totalCost + mp + ordinal.
+    pub fn message(&self) -> String {
+        format!("{}{}{}{}{}{}", self.timestamp, self.spell, self.caster_uuid, self.total_cost, self.mp, self.ordinal)
+    }
+}
+
+impl BDO {
+    /// Sets the fount user that MAGIC spells are cast as.
+    pub fn with_caster(mut self, caster: Caster) -> Self {
+        self.caster = Some(caster);
+        self
+    }
+
+    pub fn caster(&self) -> Option<&Caster> {
+        self.caster.as_ref()
+    }
+
+    /// Routes `create_user`, `update_bdo`, `save_bases` and `put_spellbook`
+    /// through a resolver (usually fount) as MAGIC spells.
+    ///
+    /// The resolver verifies the caster's signature and deducts MP before
+    /// forwarding the spell to BDO, so a caster must be set as well.
+    /// `allyabase://fount` works here like it does in `BDO::new`.
+    pub fn with_resolver(mut self, resolver_url: &str) -> Self {
+        let resolver_url = crate::allyabase::translate_url(resolver_url, &crate::allyabase::ServiceTable::default())
+            .unwrap_or_else(|_| resolver_url.to_string());
+        let resolver_url = if resolver_url.ends_with('/') {
+            resolver_url.to_string()
+        } else {
+            format!("{}/", resolver_url)
+        };
+        self.resolver_url = Some(resolver_url);
+        self
+    }
+
+    pub fn resolver_url(&self) -> Option<&str> {
+        self.resolver_url.as_deref()
+    }
+
+    fn spell_url(&self, spell_name: &str) -> String {
+        match &self.resolver_url {
+            Some(resolver_url) => format!("{}resolve/{}", resolver_url, spell_name),
+            None => format!("{}magic/spell/{}", self.base_url, spell_name),
+        }
+    }
+
+    /// Builds and signs a spell with the configured caster.
+    ///
+    /// Each call uses up one of the caster's ordinals.
+    pub fn build_spell(&self, spell_name: &str, components: Value) -> Result<Spell, BdoError> {
+        let caster = self.caster.as_ref()
+            .ok_or_else(|| BdoError::Config("casting spells requires a caster; use BDO::with_caster".to_string()))?;
+
+        let mut spell = Spell {
+            spell: spell_name.to_string(),
+            caster_uuid: caster.uuid.clone(),
+            timestamp: Self::get_timestamp(),
+            total_cost: SPELL_COST,
+            mp: true,
+            ordinal: caster.next_ordinal(),
+            components,
+            caster_signature: String::new(),
+        };
+        spell.caster_signature = self.sessionless.sign(spell.message()).to_hex();
+
+        Ok(spell)
+    }
+
+    /// Casts a spell at the resolver's `/resolve/:spellName` route, or at
+    /// BDO's `/magic/spell/:spellName` route when no resolver is set.
+    ///
+    /// A `{success: false, error}` response comes back as an `Err`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub async fn cast_spell<T: DeserializeOwned>(&self, spell: &Spell) -> Result<T, BdoError> {
+        let url = self.spell_url(&spell.spell);
+        let res = self.post(&url, json!(spell)).await?;
+        Self::parse(res).await
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub async fn cast_user_create(&self, hash: &str, bdo: &Value, is_public: &bool) -> Result<SpellUser, BdoError> {
+        let components = json!({
+            "hash": hash,
+            "bdo": bdo,
+            "pub": is_public,
+            "pubKey": self.sessionless.public_key().to_hex()
+        });
+
+        let components = &components;
+        self.retrying(Operation::Write, move || async move {
+            let spell = self.build_spell(BDO_USER_CREATE, components.clone())?;
+            self.cast_spell(&spell).await
+        }).await
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(uuid = %uuid)))]
+    pub async fn cast_user_bdo(&self, uuid: &str, hash: &str, bdo: &Value, is_public: &bool) -> Result<SpellUser, BdoError> {
+        let components = json!({
+            "uuid": uuid,
+            "hash": hash,
+            "bdo": bdo,
+            "pub": is_public,
+            "pubKey": self.sessionless.public_key().to_hex()
+        });
+
+        let components = &components;
+        self.retrying(Operation::Write, move || async move {
+            let spell = self.build_spell(BDO_USER_BDO, components.clone())?;
+            self.cast_spell(&spell).await
+        }).await
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(uuid = %uuid)))]
+    pub async fn cast_user_bases(&self, uuid: &str, hash: &str, bases: &Bases) -> Result<SpellBases, BdoError> {
+        let components = json!({
+            "uuid": uuid,
+            "hash": hash,
+            "bases": bases.bases
+        });
+
+        let components = &components;
+        self.retrying(Operation::Write, move || async move {
+            let spell = self.build_spell(BDO_USER_BASES, components.clone())?;
+            self.cast_spell(&spell).await
+        }).await
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(uuid = %uuid)))]
+    pub async fn cast_user_spellbooks(&self, uuid: &str, hash: &str, spellbook: &Spellbook) -> Result<SpellSpellbooks, BdoError> {
+        let components = json!({
+            "uuid": uuid,
+            "hash": hash,
+            "spellbook": spellbook
+        });
+
+        let components = &components;
+        self.retrying(Operation::Write, move || async move {
+            let spell = self.build_spell(BDO_USER_SPELLBOOKS, components.clone())?;
+            self.cast_spell(&spell).await
+        }).await
+    }
+}
@@ -0,0 +1,298 @@
+//! An in-memory `BdoApi` for unit tests of code that uses a BDO client.
+//! Downstream crates get it with the `mock` feature.
+//!
+//! `MockBdo` keeps BDOs, bases and spellbooks in memory, records every call,
+//! and fails calls on demand. It doesn't check signatures; use
+//! `fake::FakeBdoServer` to test against the server's REST contract instead.
+
+use indexmap::IndexMap;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::api::{BdoApi, BdoFuture};
+use crate::structs::{AddTemplateResponse, BDOUser, EmojicodeResponse, PubKeyEmojicodeResponse, ShortCodeResponse, SuccessResult, TeleportResponse, Template};
+use crate::{BdoError, Bases, Spellbook};
+
+/// The pubKey a `MockBdo` writes public BDOs under unless told otherwise.
+pub const DEFAULT_PUB_KEY: &str = "mock-pub-key";
+
+/// One call made to a `MockBdo`: the method's name and its arguments by name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MockCall {
+    pub method: &'static str,
+    pub args: Value,
+}
+
+#[derive(Default)]
+struct MockState {
+    calls: Vec<MockCall>,
+    failures: HashMap<String, VecDeque<BdoError>>,
+    users: u64,
+    bdos: HashMap<(String, String), Value>,
+    public_bdos: HashMap<String, Value>,
+    /// Emojicode to pubKey and creation time.
+    emojicodes: IndexMap<String, (String, i64)>,
+    short_codes: HashMap<String, String>,
+    bases: Bases,
+    spellbooks: Vec<Spellbook>,
+    templates: HashMap<String, Vec<String>>,
+    teleports: HashMap<String, TeleportResponse>,
+}
+
+pub struct MockBdo {
+    pub_key: String,
+    state: Mutex<MockState>,
+}
+
+impl Default for MockBdo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn not_found(message: &str) -> BdoError {
+    BdoError::NotFound { status: StatusCode::NOT_FOUND, message: message.to_string() }
+}
+
+fn ready<'a, T: Send + 'a>(result: Result<T, BdoError>) -> BdoFuture<'a, T> {
+    Box::pin(std::future::ready(result))
+}
+
+impl MockState {
+    fn emojicode_for(&mut self, pub_key: &str) -> String {
+        if let Some((emojicode, _)) = self.emojicodes.iter().find(|(_, (owner, _))| owner == pub_key) {
+            return emojicode.clone();
+        }
+
+        let emojicode = format!("mock-emojicode-{}", self.emojicodes.len() + 1);
+        let created_at = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis() as i64;
+        self.emojicodes.insert(emojicode.clone(), (pub_key.to_string(), created_at));
+        emojicode
+    }
+
+    fn save_bdo(&mut self, uuid: &str, hash: &str, bdo: &Value, public_pub_key: Option<&str>) {
+        self.bdos.insert((uuid.to_string(), hash.to_string()), bdo.clone());
+        if let Some(pub_key) = public_pub_key {
+            self.public_bdos.insert(pub_key.to_string(), bdo.clone());
+            self.emojicode_for(pub_key);
+        }
+    }
+}
+
+impl MockBdo {
+    pub fn new() -> Self {
+        MockBdo {
+            pub_key: DEFAULT_PUB_KEY.to_string(),
+            state: Mutex::new(MockState::default()),
+        }
+    }
+
+    /// The pubKey public writes are saved under, as the client's own key would be.
+    pub fn with_pub_key(mut self, pub_key: &str) -> Self {
+        self.pub_key = pub_key.to_string();
+        self
+    }
+
+    pub fn pub_key(&self) -> &str {
+        &self.pub_key
+    }
+
+    /// Every call made so far, in order.
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.lock().calls.clone()
+    }
+
+    /// The calls made to one method, e.g. `"get_bdo"`.
+    pub fn calls_to(&self, method: &str) -> Vec<MockCall> {
+        self.lock().calls.iter().filter(|call| call.method == method).cloned().collect()
+    }
+
+    pub fn clear_calls(&self) {
+        self.lock().calls.clear();
+    }
+
+    /// Makes the next call to `method` fail with `error`. Queue several to
+    /// fail several calls in a row; the call is still recorded.
+    pub fn fail_next(&self, method: &str, error: BdoError) {
+        self.lock().failures.entry(method.to_string()).or_default().push_back(error);
+    }
+
+    /// Saves a private BDO as if it had been written earlier.
+    pub fn insert_bdo(&self, uuid: &str, hash: &str, bdo: Value) {
+        self.lock().save_bdo(uuid, hash, &bdo, None);
+    }
+
+    /// Saves a public BDO for `pub_key` and returns its emojicode.
+    pub fn insert_public_bdo(&self, pub_key: &str, bdo: Value) -> String {
+        let mut state = self.lock();
+        state.public_bdos.insert(pub_key.to_string(), bdo);
+        state.emojicode_for(pub_key)
+    }
+
+    /// Gives `pub_key`'s public BDO a short code and returns it.
+    pub fn assign_short_code(&self, pub_key: &str) -> String {
+        let mut state = self.lock();
+        let short_code = format!("{:09x}", state.short_codes.len() + 1);
+        state.short_codes.insert(short_code.clone(), pub_key.to_string());
+        short_code
+    }
+
+    /// Makes teleporting to `url` answer `response`. Other URLs answer `{"valid": false}`.
+    pub fn add_teleport(&self, url: &str, response: TeleportResponse) {
+        self.lock().teleports.insert(url.to_string(), response);
+    }
+
+    /// The private BDO saved for `uuid` and `hash`.
+    pub fn bdo(&self, uuid: &str, hash: &str) -> Option<Value> {
+        self.lock().bdos.get(&(uuid.to_string(), hash.to_string())).cloned()
+    }
+
+    /// The public BDO saved for `pub_key`.
+    pub fn public_bdo(&self, pub_key: &str) -> Option<Value> {
+        self.lock().public_bdos.get(pub_key).cloned()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().expect("mock BDO lock")
+    }
+
+    /// Records a call, then runs `respond` unless a failure was queued for it.
+    fn call<T>(&self, method: &'static str, args: Value, respond: impl FnOnce(&mut MockState) -> Result<T, BdoError>) -> Result<T, BdoError> {
+        let mut state = self.lock();
+        state.calls.push(MockCall { method, args });
+        if let Some(error) = state.failures.get_mut(method).and_then(VecDeque::pop_front) {
+            return Err(error);
+        }
+
+        respond(&mut state)
+    }
+}
+
+impl BdoApi for MockBdo {
+    fn create_user<'a>(&'a self, hash: &'a str, bdo: &'a Value, is_public: &'a bool) -> BdoFuture<'a, BDOUser> {
+        ready(self.call("create_user", json!({ "hash": hash, "bdo": bdo, "isPublic": is_public }), |state| {
+            state.users += 1;
+            let uuid = format!("00000000-0000-4000-8000-{:012x}", state.users);
+            state.save_bdo(&uuid, hash, bdo, is_public.then_some(self.pub_key.as_str()));
+            Ok(BDOUser { uuid, bdo: bdo.clone() })
+        }))
+    }
+
+    fn update_bdo<'a>(&'a self, uuid: &'a str, hash: &'a str, bdo: &'a Value, is_public: &'a bool) -> BdoFuture<'a, BDOUser> {
+        ready(self.call("update_bdo", json!({ "uuid": uuid, "hash": hash, "bdo": bdo, "isPublic": is_public }), |state| {
+            state.save_bdo(uuid, hash, bdo, is_public.then_some(self.pub_key.as_str()));
+            Ok(BDOUser { uuid: uuid.to_string(), bdo: bdo.clone() })
+        }))
+    }
+
+    fn get_bdo<'a>(&'a self, uuid: &'a str, hash: &'a str) -> BdoFuture<'a, BDOUser> {
+        ready(self.call("get_bdo", json!({ "uuid": uuid, "hash": hash }), |state| {
+            // Like the server, a BDO that was never written comes back as null.
+            let bdo = state.bdos.get(&(uuid.to_string(), hash.to_string())).cloned().unwrap_or(Value::Null);
+            Ok(BDOUser { uuid: uuid.to_string(), bdo })
+        }))
+    }
+
+    fn get_public_bdo<'a>(&'a self, uuid: &'a str, hash: &'a str, pub_key: &'a str) -> BdoFuture<'a, BDOUser> {
+        ready(self.call("get_public_bdo", json!({ "uuid": uuid, "hash": hash, "pubKey": pub_key }), |state| {
+            let bdo = state.public_bdos.get(pub_key).cloned().unwrap_or(Value::Null);
+            Ok(BDOUser { uuid: uuid.to_string(), bdo })
+        }))
+    }
+
+    fn get_bases<'a>(&'a self, uuid: &'a str, hash: &'a str) -> BdoFuture<'a, Bases> {
+        ready(self.call("get_bases", json!({ "uuid": uuid, "hash": hash }), |state| Ok(state.bases.clone())))
+    }
+
+    fn save_bases<'a>(&'a self, uuid: &'a str, hash: &'a str, bases: &'a Bases) -> BdoFuture<'a, Bases> {
+        ready(self.call("save_bases", json!({ "uuid": uuid, "hash": hash, "bases": bases.bases }), |state| {
+            state.bases.bases.extend(bases.bases.clone());
+            Ok(state.bases.clone())
+        }))
+    }
+
+    fn get_spellbooks<'a>(&'a self, uuid: &'a str, hash: &'a str) -> BdoFuture<'a, Vec<Spellbook>> {
+        ready(self.call("get_spellbooks", json!({ "uuid": uuid, "hash": hash }), |state| Ok(state.spellbooks.clone())))
+    }
+
+    fn put_spellbook<'a>(&'a self, uuid: &'a str, hash: &'a str, spellbook: &'a Spellbook) -> BdoFuture<'a, Vec<Spellbook>> {
+        ready(self.call("put_spellbook", json!({ "uuid": uuid, "hash": hash, "spellbook": spellbook }), |state| {
+            state.spellbooks.push(spellbook.clone());
+            Ok(state.spellbooks.clone())
+        }))
+    }
+
+    fn delete_user<'a>(&'a self, uuid: &'a str, hash: &'a str) -> BdoFuture<'a, SuccessResult> {
+        ready(self.call("delete_user", json!({ "uuid": uuid, "hash": hash }), |state| {
+            state.bdos.retain(|(owner, _), _| owner != uuid);
+            Ok(SuccessResult { success: true })
+        }))
+    }
+
+    fn teleport<'a>(&'a self, uuid: &'a str, hash: &'a str, url: &'a str) -> BdoFuture<'a, TeleportResponse> {
+        ready(self.call("teleport", json!({ "uuid": uuid, "hash": hash, "url": url }), |state| {
+            Ok(state.teleports.get(url).cloned().unwrap_or_default())
+        }))
+    }
+
+    fn get_bdo_by_emojicode<'a>(&'a self, emojicode: &'a str) -> BdoFuture<'a, EmojicodeResponse> {
+        ready(self.call("get_bdo_by_emojicode", json!({ "emojicode": emojicode }), |state| {
+            let (pub_key, created_at) = state.emojicodes.get(emojicode).cloned().ok_or_else(|| not_found("Emojicode not found"))?;
+            let bdo = state.public_bdos.get(&pub_key).cloned().ok_or_else(|| not_found("BDO not found"))?;
+            Ok(EmojicodeResponse { emojicode: emojicode.to_string(), pub_key, bdo, created_at: Some(created_at) })
+        }))
+    }
+
+    fn get_bdo_by_short_code<'a>(&'a self, short_code: &'a str) -> BdoFuture<'a, ShortCodeResponse> {
+        ready(self.call("get_bdo_by_short_code", json!({ "shortCode": short_code }), |state| {
+            let pub_key = state.short_codes.get(short_code).cloned().ok_or_else(|| not_found("Short code not found"))?;
+            let bdo = state.public_bdos.get(&pub_key).cloned().ok_or_else(|| not_found("BDO not found"))?;
+            Ok(ShortCodeResponse { short_code: short_code.to_string(), pub_key, bdo })
+        }))
+    }
+
+    fn get_emojicode_for_pub_key<'a>(&'a self, pub_key: &'a str) -> BdoFuture<'a, PubKeyEmojicodeResponse> {
+        ready(self.call("get_emojicode_for_pub_key", json!({ "pubKey": pub_key }), |state| {
+            state.emojicodes.iter()
+                .find(|(_, (owner, _))| owner == pub_key)
+                .map(|(emojicode, (_, created_at))| PubKeyEmojicodeResponse {
+                    pub_key: pub_key.to_string(),
+                    emojicode: emojicode.clone(),
+                    created_at: Some(*created_at),
+                })
+                .ok_or_else(|| not_found("Emojicode not found for this pubKey"))
+        }))
+    }
+
+    fn get_templates<'a>(&'a self, hash: &'a str) -> BdoFuture<'a, Vec<Template>> {
+        ready(self.call("get_templates", json!({ "hash": hash }), |state| {
+            Ok(state.templates.get(hash).into_iter().flatten()
+                .filter_map(|emojicode| {
+                    let (pub_key, _) = state.emojicodes.get(emojicode)?;
+                    Some(Template {
+                        emojicode: emojicode.clone(),
+                        pub_key: pub_key.clone(),
+                        bdo: state.public_bdos.get(pub_key)?.clone(),
+                    })
+                })
+                .collect())
+        }))
+    }
+
+    fn add_template<'a>(&'a self, hash: &'a str, emojicode: &'a str) -> BdoFuture<'a, AddTemplateResponse> {
+        ready(self.call("add_template", json!({ "hash": hash, "emojicode": emojicode }), |state| {
+            let templates = state.templates.entry(hash.to_string()).or_default();
+            if !templates.iter().any(|known| known == emojicode) {
+                templates.push(emojicode.to_string());
+            }
+            Ok(AddTemplateResponse {
+                success: true,
+                hash: hash.to_string(),
+                emojicode: emojicode.to_string(),
+                message: "Template added to index".to_string(),
+            })
+        }))
+    }
+}
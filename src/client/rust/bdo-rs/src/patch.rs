@@ -0,0 +1,259 @@
+//! Partial BDO updates with JSON Patch (RFC 6902) and JSON Merge Patch
+//! (RFC 7386), and diffs that produce them.
+//!
+//! `patch_bdo` and `merge_patch_bdo` apply a patch to the latest stored copy
+//! and swap the result in with `update_bdo_with`, so a patch is never applied
+//! to a stale BDO and a failed `test` operation writes nothing.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::canonical::to_canonical_string;
+use crate::structs::BDOUser;
+use crate::{BdoError, BDO};
+
+/// One JSON Patch operation, e.g. `{"op": "replace", "path": "/name", "value": "New"}`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+fn invalid(message: String) -> BdoError {
+    BdoError::Patch(message)
+}
+
+/// Splits a JSON Pointer into its unescaped tokens. `""` is the whole document.
+fn parse_pointer(pointer: &str) -> Result<Vec<String>, BdoError> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    let Some(tokens) = pointer.strip_prefix('/') else {
+        return Err(invalid(format!("{} isn't a JSON Pointer", pointer)));
+    };
+
+    Ok(tokens.split('/').map(|token| token.replace("~1", "/").replace("~0", "~")).collect())
+}
+
+fn escape_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+/// An array index token: digits without leading zeros.
+fn parse_index(token: &str, pointer: &str) -> Result<usize, BdoError> {
+    let is_index = !token.is_empty() && token.chars().all(|c| c.is_ascii_digit()) && (token == "0" || !token.starts_with('0'));
+    is_index.then(|| token.parse().ok()).flatten()
+        .ok_or_else(|| invalid(format!("{} isn't an array index in {}", token, pointer)))
+}
+
+fn get<'a>(doc: &'a Value, tokens: &[String], pointer: &str) -> Result<&'a Value, BdoError> {
+    tokens.iter().try_fold(doc, |value, token| {
+        let found = match value {
+            Value::Object(map) => map.get(token),
+            Value::Array(items) => items.get(parse_index(token, pointer)?),
+            _ => None,
+        };
+        found.ok_or_else(|| invalid(format!("{} doesn't exist", pointer)))
+    })
+}
+
+fn get_mut<'a>(doc: &'a mut Value, tokens: &[String], pointer: &str) -> Result<&'a mut Value, BdoError> {
+    let mut value = doc;
+    for token in tokens {
+        value = match value {
+            Value::Object(map) => map.get_mut(token),
+            Value::Array(items) => items.get_mut(parse_index(token, pointer)?),
+            _ => None,
+        }.ok_or_else(|| invalid(format!("{} doesn't exist", pointer)))?;
+    }
+    Ok(value)
+}
+
+fn add(doc: &mut Value, pointer: &str, value: Value) -> Result<(), BdoError> {
+    let tokens = parse_pointer(pointer)?;
+    let Some((last, parent)) = tokens.split_last() else {
+        *doc = value;
+        return Ok(());
+    };
+
+    match get_mut(doc, parent, pointer)? {
+        Value::Object(map) => {
+            map.insert(last.clone(), value);
+        },
+        Value::Array(items) if last == "-" => items.push(value),
+        Value::Array(items) => {
+            let index = parse_index(last, pointer)?;
+            if index > items.len() {
+                return Err(invalid(format!("{} is past the end of the array", pointer)));
+            }
+            items.insert(index, value);
+        },
+        _ => return Err(invalid(format!("the parent of {} isn't an object or array", pointer))),
+    }
+    Ok(())
+}
+
+fn remove(doc: &mut Value, pointer: &str) -> Result<Value, BdoError> {
+    let tokens = parse_pointer(pointer)?;
+    let Some((last, parent)) = tokens.split_last() else {
+        return Err(invalid("the whole document can't be removed".to_string()));
+    };
+
+    let removed = match get_mut(doc, parent, pointer)? {
+        Value::Object(map) => map.remove(last),
+        Value::Array(items) => {
+            let index = parse_index(last, pointer)?;
+            (index < items.len()).then(|| items.remove(index))
+        },
+        _ => None,
+    };
+    removed.ok_or_else(|| invalid(format!("{} doesn't exist", pointer)))
+}
+
+fn apply_operation(doc: &mut Value, operation: &PatchOperation) -> Result<(), BdoError> {
+    match operation {
+        PatchOperation::Add { path, value } => add(doc, path, value.clone()),
+        PatchOperation::Remove { path } => remove(doc, path).map(|_| ()),
+        PatchOperation::Replace { path, value } => {
+            *get_mut(doc, &parse_pointer(path)?, path)? = value.clone();
+            Ok(())
+        },
+        PatchOperation::Move { from, path } => {
+            if path.starts_with(from.as_str()) && path[from.len()..].starts_with('/') {
+                return Err(invalid(format!("{} can't be moved into its own child {}", from, path)));
+            }
+            let value = remove(doc, from)?;
+            add(doc, path, value)
+        },
+        PatchOperation::Copy { from, path } => {
+            let value = get(doc, &parse_pointer(from)?, from)?.clone();
+            add(doc, path, value)
+        },
+        PatchOperation::Test { path, value } => {
+            // Numbers compare by value, so `1` passes a test for `1.0`.
+            let found = get(doc, &parse_pointer(path)?, path)?;
+            if to_canonical_string(found) == to_canonical_string(value) {
+                Ok(())
+            } else {
+                Err(invalid(format!("test failed at {}", path)))
+            }
+        },
+    }
+}
+
+/// Applies a JSON Patch to a copy of `doc`. Either every operation applies
+/// or the patch fails as a whole.
+pub fn apply_patch(doc: &Value, patch: &[PatchOperation]) -> Result<Value, BdoError> {
+    let mut patched = doc.clone();
+    for operation in patch {
+        apply_operation(&mut patched, operation)?;
+    }
+    Ok(patched)
+}
+
+/// Applies a JSON Merge Patch: objects merge key by key, `null` removes a
+/// key, and anything else replaces what was there.
+pub fn apply_merge_patch(doc: &Value, patch: &Value) -> Value {
+    let Value::Object(patch) = patch else {
+        return patch.clone();
+    };
+
+    let mut merged = match doc {
+        Value::Object(map) => map.clone(),
+        _ => Map::new(),
+    };
+    for (key, value) in patch {
+        if value.is_null() {
+            merged.remove(key);
+        } else {
+            let patched = apply_merge_patch(merged.get(key).unwrap_or(&Value::Null), value);
+            merged.insert(key.clone(), patched);
+        }
+    }
+    Value::Object(merged)
+}
+
+/// The JSON Patch that turns `from` into `to`. Arrays are patched by index:
+/// elements are compared in place, then the tail is added or removed.
+pub fn diff(from: &Value, to: &Value) -> Vec<PatchOperation> {
+    let mut patch = Vec::new();
+    diff_into(from, to, "", &mut patch);
+    patch
+}
+
+fn diff_into(from: &Value, to: &Value, path: &str, patch: &mut Vec<PatchOperation>) {
+    match (from, to) {
+        (Value::Object(from), Value::Object(to)) => {
+            for key in from.keys().filter(|key| !to.contains_key(*key)) {
+                patch.push(PatchOperation::Remove { path: format!("{}/{}", path, escape_token(key)) });
+            }
+            for (key, value) in to {
+                let child = format!("{}/{}", path, escape_token(key));
+                match from.get(key) {
+                    Some(old) => diff_into(old, value, &child, patch),
+                    None => patch.push(PatchOperation::Add { path: child, value: value.clone() }),
+                }
+            }
+        },
+        (Value::Array(from), Value::Array(to)) => {
+            for (index, (old, new)) in from.iter().zip(to).enumerate() {
+                diff_into(old, new, &format!("{}/{}", path, index), patch);
+            }
+            // Removed from the end first, so the earlier indexes stay put.
+            for index in (to.len()..from.len()).rev() {
+                patch.push(PatchOperation::Remove { path: format!("{}/{}", path, index) });
+            }
+            for (index, value) in to.iter().enumerate().skip(from.len()) {
+                patch.push(PatchOperation::Add { path: format!("{}/{}", path, index), value: value.clone() });
+            }
+        },
+        _ if to_canonical_string(from) != to_canonical_string(to) => {
+            patch.push(PatchOperation::Replace { path: path.to_string(), value: to.clone() });
+        },
+        _ => {},
+    }
+}
+
+/// The JSON Merge Patch that turns `from` into `to`. Merge patches can't
+/// set a key to `null` or change part of an array, so those are replaced
+/// whole; `apply_merge_patch(from, &merge_diff(from, to))` equals `to`
+/// unless `to` holds `null`s.
+pub fn merge_diff(from: &Value, to: &Value) -> Value {
+    let (Value::Object(from), Value::Object(to)) = (from, to) else {
+        return to.clone();
+    };
+
+    let mut patch = Map::new();
+    for key in from.keys().filter(|key| !to.contains_key(*key)) {
+        patch.insert(key.clone(), Value::Null);
+    }
+    for (key, value) in to {
+        match from.get(key) {
+            Some(old) if to_canonical_string(old) == to_canonical_string(value) => {},
+            Some(old) if old.is_object() && value.is_object() => {
+                patch.insert(key.clone(), merge_diff(old, value));
+            },
+            _ => {
+                patch.insert(key.clone(), value.clone());
+            },
+        }
+    }
+    Value::Object(patch)
+}
+
+impl BDO {
+    /// Applies a JSON Patch to the latest stored BDO and writes the result,
+    /// applying it again if someone else writes in between.
+    pub async fn patch_bdo(&self, uuid: &str, hash: &str, patch: &[PatchOperation], is_public: &bool) -> Result<BDOUser, BdoError> {
+        self.update_bdo_with(uuid, hash, is_public, |current| apply_patch(current, patch)).await
+    }
+
+    /// Applies a JSON Merge Patch to the latest stored BDO and writes the result.
+    pub async fn merge_patch_bdo(&self, uuid: &str, hash: &str, patch: &Value, is_public: &bool) -> Result<BDOUser, BdoError> {
+        self.update_bdo_with(uuid, hash, is_public, |current| Ok(apply_merge_patch(current, patch))).await
+    }
+}
@@ -0,0 +1,127 @@
+use rand::Rng;
+use reqwest::StatusCode;
+use std::future::Future;
+use std::time::Duration;
+use crate::{BdoError, BDO};
+
+/// When and how often a failed request is tried again.
+///
+/// Reads are retried by default; writes only when `retry_writes` is set,
+/// since a write whose response got lost may already have been applied.
+/// Every attempt is signed again with a fresh timestamp.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first one. `1` disables retries.
+    pub max_attempts: u32,
+    /// The wait before the first retry; it doubles with every retry after that.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Randomizes each wait between half and all of the backoff.
+    pub jitter: bool,
+    /// Statuses that are worth retrying.
+    pub transient_statuses: Vec<StatusCode>,
+    pub retry_writes: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+            jitter: true,
+            transient_statuses: vec![
+                StatusCode::REQUEST_TIMEOUT,
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            retry_writes: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retries.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Retries writes as well as reads.
+    pub fn with_writes(mut self) -> Self {
+        self.retry_writes = true;
+        self
+    }
+
+    /// Whether an error is likely to go away if the request is sent again.
+    pub fn is_transient(&self, err: &BdoError) -> bool {
+        match err {
+            // Bodies are decoded by serde_json, not reqwest, so a reqwest decode
+            // error is a body that was cut off while `text()` read it.
+            BdoError::Transport(err) => err.is_timeout() || err.is_connect() || err.is_body() || err.is_decode(),
+            BdoError::Status { status, .. } => self.transient_statuses.contains(status),
+            _ => false,
+        }
+    }
+
+    /// How long to wait before retry number `retry`, starting at 1.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_backoff);
+
+        if self.jitter {
+            backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+        } else {
+            backoff
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Operation {
+    Read,
+    Write,
+}
+
+impl BDO {
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// Runs `attempt` until it succeeds, fails for good, or runs out of attempts.
+    pub(crate) async fn retrying<T, F, Fut>(&self, operation: Operation, attempt: F) -> Result<T, BdoError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, BdoError>>,
+    {
+        let policy = &self.retry_policy;
+        let max_attempts = if operation == Operation::Write && !policy.retry_writes {
+            1
+        } else {
+            policy.max_attempts.max(1)
+        };
+
+        let mut attempts = 1;
+        loop {
+            match attempt().await {
+                Err(err) if attempts < max_attempts && policy.is_transient(&err) => {
+                    crate::trace::retry(attempts, &err);
+                    tokio::time::sleep(policy.backoff(attempts)).await;
+                    attempts += 1;
+                },
+                result => return result,
+            }
+        }
+    }
+}
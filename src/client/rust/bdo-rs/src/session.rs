@@ -0,0 +1,122 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use crate::patch::PatchOperation;
+use crate::structs::{BDOUser, SuccessResult, TeleportResponse};
+use crate::teleport::VerifiedTeleport;
+use crate::{BdoError, Bases, Cached, Spellbook, BDO};
+
+/// A user's uuid and app hash bound to a `BDO` client, so they don't have
+/// to be passed to every call.
+pub struct BdoSession<'a> {
+    bdo: &'a BDO,
+    uuid: String,
+    hash: String,
+}
+
+impl<'a> BdoSession<'a> {
+    pub fn new(bdo: &'a BDO, uuid: &str, hash: &str) -> Self {
+        BdoSession {
+            bdo,
+            uuid: uuid.to_string(),
+            hash: hash.to_string(),
+        }
+    }
+
+    pub fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    pub fn client(&self) -> &'a BDO {
+        self.bdo
+    }
+
+    pub async fn get_bdo(&self) -> Result<BDOUser, BdoError> {
+        self.bdo.get_bdo(&self.uuid, &self.hash).await
+    }
+
+    pub async fn get_bdo_cached(&self) -> Result<Cached<BDOUser>, BdoError> {
+        self.bdo.get_bdo_cached(&self.uuid, &self.hash).await
+    }
+
+    pub async fn get_public_bdo(&self, pub_key: &str) -> Result<BDOUser, BdoError> {
+        self.bdo.get_public_bdo(&self.uuid, &self.hash, pub_key).await
+    }
+
+    pub async fn update_bdo(&self, bdo: &Value, is_public: &bool) -> Result<BDOUser, BdoError> {
+        self.bdo.update_bdo(&self.uuid, &self.hash, bdo, is_public).await
+    }
+
+    pub async fn update_bdo_with<F>(&self, is_public: &bool, update: F) -> Result<BDOUser, BdoError>
+    where
+        F: FnMut(&Value) -> Result<Value, BdoError>,
+    {
+        self.bdo.update_bdo_with(&self.uuid, &self.hash, is_public, update).await
+    }
+
+    pub async fn patch_bdo(&self, patch: &[PatchOperation], is_public: &bool) -> Result<BDOUser, BdoError> {
+        self.bdo.patch_bdo(&self.uuid, &self.hash, patch, is_public).await
+    }
+
+    pub async fn merge_patch_bdo(&self, patch: &Value, is_public: &bool) -> Result<BDOUser, BdoError> {
+        self.bdo.merge_patch_bdo(&self.uuid, &self.hash, patch, is_public).await
+    }
+
+    pub async fn get_bdo_as<T: DeserializeOwned>(&self) -> Result<BDOUser<T>, BdoError> {
+        self.bdo.get_bdo_as(&self.uuid, &self.hash).await
+    }
+
+    pub async fn get_public_bdo_as<T: DeserializeOwned>(&self, pub_key: &str) -> Result<BDOUser<T>, BdoError> {
+        self.bdo.get_public_bdo_as(&self.uuid, &self.hash, pub_key).await
+    }
+
+    pub async fn update_bdo_typed<T: Serialize + DeserializeOwned>(&self, bdo: &T, is_public: &bool) -> Result<BDOUser<T>, BdoError> {
+        self.bdo.update_bdo_typed(&self.uuid, &self.hash, bdo, is_public).await
+    }
+
+    pub async fn get_bases(&self) -> Result<Bases, BdoError> {
+        self.bdo.get_bases(&self.uuid, &self.hash).await
+    }
+
+    pub async fn save_bases(&self, bases: &Bases) -> Result<Bases, BdoError> {
+        self.bdo.save_bases(&self.uuid, &self.hash, bases).await
+    }
+
+    pub async fn get_spellbooks(&self) -> Result<Vec<Spellbook>, BdoError> {
+        self.bdo.get_spellbooks(&self.uuid, &self.hash).await
+    }
+
+    pub async fn put_spellbook(&self, spellbook: &Spellbook) -> Result<Vec<Spellbook>, BdoError> {
+        self.bdo.put_spellbook(&self.uuid, &self.hash, spellbook).await
+    }
+
+    pub async fn teleport(&self, url: &str) -> Result<TeleportResponse, BdoError> {
+        self.bdo.teleport(&self.uuid, &self.hash, url).await
+    }
+
+    pub async fn teleport_verified(&self, url: &str) -> Result<VerifiedTeleport, BdoError> {
+        self.bdo.teleport_verified(&self.uuid, &self.hash, url).await
+    }
+
+    pub async fn delete_user(&self) -> Result<SuccessResult, BdoError> {
+        self.bdo.delete_user(&self.uuid, &self.hash).await
+    }
+}
+
+impl BDO {
+    /// A session for a user that already exists.
+    pub fn session(&self, uuid: &str, hash: &str) -> BdoSession<'_> {
+        BdoSession::new(self, uuid, hash)
+    }
+
+    /// Creates a user like `create_user` and returns a session bound to it.
+    pub async fn create_user_session(&self, hash: &str, bdo: &Value, is_public: &bool) -> Result<BdoSession<'_>, BdoError> {
+        let user = self.create_user(hash, bdo, is_public).await?;
+
+        Ok(BdoSession::new(self, &user.uuid, hash))
+    }
+}
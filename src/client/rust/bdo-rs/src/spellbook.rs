@@ -0,0 +1,172 @@
+use indexmap::IndexMap;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+use crate::Spellbook;
+
+/// The key a spellbook keeps its name under, which can't be used as a spell name.
+pub const SPELLBOOK_NAME_KEY: &str = "spellbookName";
+
+/// A spell as a spellbook defines it: what it costs and which services it
+/// stops at on its way through the resolver.
+///
+/// Not to be confused with `bdo_rs::Spell`, which is a signed casting of one.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct Spell {
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub cost: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub destinations: Vec<Destination>,
+    /// The service that resolves the spell, e.g. `fount`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolver: Option<String>,
+    /// Whether the cost is paid in MP.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub mp: bool,
+    /// Fields this client doesn't know about, kept so they survive a round trip.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+fn is_zero(cost: &u64) -> bool {
+    *cost == 0
+}
+
+/// One stop on a spell's route.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Destination {
+    #[serde(rename = "stopName")]
+    pub stop_name: String,
+    #[serde(rename = "stopURL")]
+    pub stop_url: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Spell {
+    pub fn new(cost: u64, destinations: Vec<Destination>) -> Self {
+        Spell {
+            cost,
+            destinations,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_resolver(mut self, resolver: &str) -> Self {
+        self.resolver = Some(resolver.to_string());
+        self
+    }
+
+    pub fn with_mp(mut self, mp: bool) -> Self {
+        self.mp = mp;
+        self
+    }
+
+    /// The destination for `stop_name`, e.g. `bdo`.
+    pub fn destination(&self, stop_name: &str) -> Option<&Destination> {
+        self.destinations.iter().find(|destination| destination.stop_name == stop_name)
+    }
+}
+
+impl Destination {
+    pub fn new(stop_name: &str, stop_url: &str) -> Self {
+        Destination {
+            stop_name: stop_name.to_string(),
+            stop_url: stop_url.to_string(),
+            extra: Map::new(),
+        }
+    }
+}
+
+impl Spellbook {
+    pub fn new(name: &str) -> Self {
+        Spellbook {
+            spellbookName: name.to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.spellbookName
+    }
+
+    pub fn spell(&self, name: &str) -> Option<&Spell> {
+        self.spells.get(name)
+    }
+
+    pub fn spell_mut(&mut self, name: &str) -> Option<&mut Spell> {
+        self.spells.get_mut(name)
+    }
+
+    /// Adds a spell at the end of the spellbook. Returns `false`, leaving the
+    /// spellbook untouched, when a spell by that name already exists or the
+    /// name is `spellbookName`.
+    pub fn add_spell(&mut self, name: &str, spell: Spell) -> bool {
+        if name == SPELLBOOK_NAME_KEY || self.spells.contains_key(name) || self.extra.contains_key(name) {
+            return false;
+        }
+
+        self.spells.insert(name.to_string(), spell);
+        true
+    }
+
+    /// Replaces the spell called `name` in place, or adds it at the end, and
+    /// returns the spell it replaced. A key that wasn't a spell is dropped
+    /// from `extra`. `spellbookName` is never added.
+    pub fn replace_spell(&mut self, name: &str, spell: Spell) -> Option<Spell> {
+        if name == SPELLBOOK_NAME_KEY {
+            return None;
+        }
+        self.extra.remove(name);
+
+        self.spells.insert(name.to_string(), spell)
+    }
+
+    /// Removes a spell, keeping the others in order.
+    pub fn remove_spell(&mut self, name: &str) -> Option<Spell> {
+        self.spells.shift_remove(name)
+    }
+}
+
+impl Serialize for Spellbook {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(1 + self.spells.len() + self.extra.len()))?;
+        map.serialize_entry(SPELLBOOK_NAME_KEY, &self.spellbookName)?;
+        for (name, spell) in &self.spells {
+            map.serialize_entry(name, spell)?;
+        }
+        for (key, value) in &self.extra {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Spellbook {
+    /// Objects that read as spells become spells; every other key, like a
+    /// note or a version number, goes to `extra`.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut fields = IndexMap::<String, Value>::deserialize(deserializer)?;
+        let name = match fields.shift_remove(SPELLBOOK_NAME_KEY) {
+            Some(Value::String(name)) => name,
+            Some(_) => return Err(serde::de::Error::custom("spellbookName must be a string")),
+            None => return Err(serde::de::Error::missing_field(SPELLBOOK_NAME_KEY)),
+        };
+
+        let mut spells = IndexMap::new();
+        let mut extra = Map::new();
+        for (key, value) in fields {
+            match value.is_object().then(|| Spell::deserialize(&value).ok()).flatten() {
+                Some(spell) => {
+                    spells.insert(key, spell);
+                },
+                None => {
+                    extra.insert(key, value);
+                },
+            }
+        }
+
+        Ok(Spellbook { spellbookName: name, spells, extra })
+    }
+}
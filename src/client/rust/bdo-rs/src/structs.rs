@@ -1,13 +1,13 @@
 use serde::{Deserialize, Serialize};
-use serde_json::json;
-use std::collections::HashMap;
-use serde_json::Value;
+use indexmap::IndexMap;
+use serde_json::{Map, Value};
+use crate::{BaseConfig, Spellbook};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all="camelCase")]
-pub struct BDOUser {
+pub struct BDOUser<T = Value> {
     pub uuid: String,
-    pub bdo: Value
+    pub bdo: T
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -17,10 +17,113 @@ pub struct SuccessResult {
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all="camelCase")]
-pub struct EmojicodeResponse {
+pub struct EmojicodeResponse<T = Value> {
     pub emojicode: String,
     pub pub_key: String,
+    pub bdo: T,
+    /// Emojicodes assigned before creation times were recorded come back without one.
+    pub created_at: Option<i64>
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct ShortCodeResponse<T = Value> {
+    pub short_code: String,
+    pub pub_key: String,
+    pub bdo: T
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct PubKeyEmojicodeResponse {
+    pub pub_key: String,
+    pub emojicode: String,
+    /// Emojicodes assigned before creation times were recorded come back without one.
+    pub created_at: Option<i64>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct Template {
+    pub emojicode: String,
+    pub pub_key: String,
+    #[serde(flatten)]
+    pub bdo: Value
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct Templates {
+    pub success: bool,
+    pub hash: String,
+    pub templates: Vec<Template>,
+    pub count: usize
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct AddTemplateResponse {
+    pub success: bool,
+    pub hash: String,
+    pub emojicode: String,
+    pub message: String
+}
+
+/// What `teleport` returns. Only `valid` is set when the teleport tag was
+/// missing or its signature didn't check out.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct TeleportResponse {
+    pub valid: bool,
+    /// The `<teleport>` tag; see `teleport::parse_teleport`.
+    #[serde(default)]
+    pub html: Option<String>,
+    /// The message the teleporter signed.
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub signature: Option<String>,
+    #[serde(default)]
+    pub teleporter_pub_key: Option<String>,
+    #[serde(default)]
+    pub amount: Option<Value>,
+    #[serde(default)]
+    pub spell: Option<Value>,
+    /// Any other attributes of the teleport tag.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct SpellUser {
+    pub success: bool,
+    pub uuid: String,
     pub bdo: Value,
-    pub created_at: i64
+    /// Only set when the BDO was saved as public.
+    pub emoji_shortcode: Option<String>
+}
+
+impl From<SpellUser> for BDOUser {
+    fn from(spell_user: SpellUser) -> Self {
+        BDOUser {
+            uuid: spell_user.uuid,
+            bdo: spell_user.bdo
+        }
+    }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct SpellBases {
+    pub success: bool,
+    pub bases: IndexMap<String, BaseConfig>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct SpellSpellbooks {
+    pub success: bool,
+    pub spellbooks: Vec<Spellbook>
+}
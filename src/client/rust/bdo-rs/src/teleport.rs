@@ -0,0 +1,450 @@
+//! Parsing the `<teleport>` HTML that teleportation returns.
+//!
+//! Teleported pages are written by hand as often as not, so this is a
+//! forgiving parser: unclosed tags are closed for you, stray closing tags are
+//! ignored, and void elements like `<img>` need no closing slash.
+
+use indexmap::IndexMap;
+use reqwest::Url;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sessionless::hex::FromHex;
+use sessionless::{PublicKey, Signature};
+use crate::structs::TeleportResponse;
+use crate::{BdoError, BDO};
+
+/// An element of teleported HTML.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TeleportElement {
+    /// The tag name, lowercased.
+    pub name: String,
+    /// Attributes in document order, with entities decoded.
+    pub attributes: IndexMap<String, String>,
+    pub children: Vec<TeleportElement>,
+    /// The element's own text, with entities decoded and whitespace trimmed.
+    pub text: String,
+}
+
+impl TeleportElement {
+    fn new(name: &str, attributes: IndexMap<String, String>) -> Self {
+        TeleportElement {
+            name: name.to_string(),
+            attributes,
+            ..Self::default()
+        }
+    }
+
+    /// An attribute's value. Attribute names are matched case-insensitively.
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn child(&self, name: &str) -> Option<&TeleportElement> {
+        self.children.iter().find(|child| child.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a TeleportElement> + 'a {
+        self.children.iter().filter(move |child| child.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The text of the first child called `name`, e.g. a teleportal's `<name>`.
+    pub fn child_text(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|child| child.text.as_str())
+    }
+
+    /// The first element called `name` at or below this one, depth first.
+    pub fn find(&self, name: &str) -> Option<&TeleportElement> {
+        if self.name.eq_ignore_ascii_case(name) {
+            return Some(self);
+        }
+
+        self.children.iter().find_map(|child| child.find(name))
+    }
+
+    /// The `<teleportal>` entries of a teleport tag, such as the products
+    /// Sanora's `teleportable-products` page lists.
+    pub fn teleportals(&self) -> impl Iterator<Item = &TeleportElement> {
+        self.children_named("teleportal")
+    }
+}
+
+/// Finds the `<teleport>` tag in `html` and parses it.
+pub fn parse_teleport(html: &str) -> Result<TeleportElement, BdoError> {
+    parse_html(html)
+        .find("teleport")
+        .cloned()
+        .ok_or_else(|| BdoError::Teleport("no <teleport> tag in the teleported content".to_string()))
+}
+
+impl TeleportResponse {
+    /// Parses `html`. Invalid teleports carry no content, so they fail too.
+    pub fn content(&self) -> Result<TeleportElement, BdoError> {
+        match &self.html {
+            Some(html) if self.valid => parse_teleport(html),
+            _ => Err(BdoError::Teleport("the teleport tag was not valid".to_string())),
+        }
+    }
+}
+
+/// The SHA-256, in hex, of everything between `<teleport ...>` and
+/// `</teleport>` in `html`, exactly as written.
+///
+/// Teleporters sign a `timestamp:content:teleport` message, which names the
+/// content but doesn't cover the HTML. A teleporter that puts this hash in
+/// its message, e.g. `timestamp:{contentHash}:teleport`, vouches for the
+/// HTML too; see `BDO::with_teleport_content_hashes`.
+pub fn teleport_content_hash(html: &str) -> Result<String, BdoError> {
+    let body = teleport_body(html)
+        .ok_or_else(|| BdoError::Teleport("no <teleport> tag in the teleported content".to_string()))?;
+
+    Ok(hex::encode(Sha256::digest(body.as_bytes())))
+}
+
+/// The raw HTML inside the first `<teleport>` tag.
+fn teleport_body(html: &str) -> Option<&str> {
+    let lowercase = html.to_ascii_lowercase();
+    let start = lowercase.match_indices("<teleport")
+        .map(|(start, _)| start)
+        .find(|start| lowercase[start + 9..].starts_with(|c: char| c.is_whitespace() || c == '>' || c == '/'))?;
+
+    // The end of the opening tag, skipping any `>` inside quoted attributes.
+    let mut quote = None;
+    let mut tag_end = None;
+    for (index, c) in html[start..].char_indices() {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), _) => {},
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => {
+                tag_end = Some(start + index);
+                break;
+            },
+            _ => {},
+        }
+    }
+    let tag_end = tag_end?;
+    if html[..tag_end].ends_with('/') {
+        return Some("");
+    }
+
+    let body_start = tag_end + 1;
+    let body_end = lowercase[body_start..].find("</teleport").map_or(html.len(), |end| body_start + end);
+    Some(&html[body_start..body_end])
+}
+
+/// A teleport whose signature this client checked itself, against the
+/// pubKey the caller expected. The only ways to get one are
+/// `BDO::verify_teleport` and `BDO::teleport_verified`.
+///
+/// The teleporter signs `message`, so that is what's vouched for; the tag's
+/// own `pubKey`, `signature` and `message` attributes must agree with it.
+/// The HTML is only vouched for when the message also carries its
+/// `teleport_content_hash`.
+#[derive(Clone, Debug)]
+pub struct VerifiedTeleport {
+    response: TeleportResponse,
+    content: TeleportElement,
+    content_signed: bool,
+}
+
+impl VerifiedTeleport {
+    /// The parsed `<teleport>` tag, if the signed message carries its
+    /// `teleport_content_hash`.
+    pub fn content(&self) -> Option<&TeleportElement> {
+        self.content_signed.then_some(&self.content)
+    }
+
+    /// The parsed `<teleport>` tag, whether or not the signature covers it.
+    /// Unless `content` returns it too, the server could have swapped it.
+    pub fn unsigned_content(&self) -> &TeleportElement {
+        &self.content
+    }
+
+    pub fn is_content_signed(&self) -> bool {
+        self.content_signed
+    }
+
+    pub fn html(&self) -> &str {
+        self.response.html.as_deref().unwrap_or_default()
+    }
+
+    pub fn message(&self) -> &str {
+        self.response.message.as_deref().unwrap_or_default()
+    }
+
+    pub fn signature(&self) -> &str {
+        self.response.signature.as_deref().unwrap_or_default()
+    }
+
+    pub fn teleporter_pub_key(&self) -> &str {
+        self.response.teleporter_pub_key.as_deref().unwrap_or_default()
+    }
+
+    pub fn amount(&self) -> Option<&Value> {
+        self.response.amount.as_ref()
+    }
+
+    pub fn spell(&self) -> Option<&Value> {
+        self.response.spell.as_ref()
+    }
+
+    pub fn into_response(self) -> TeleportResponse {
+        self.response
+    }
+}
+
+/// The `pubKey` query parameter of a teleport URL, which names who should
+/// have signed the content.
+pub fn expected_pub_key(url: &str) -> Option<String> {
+    Url::parse(url).ok()?
+        .query_pairs()
+        .find(|(key, _)| key == "pubKey")
+        .map(|(_, value)| value.into_owned())
+}
+
+fn unverified(reason: &str) -> BdoError {
+    BdoError::Teleport(format!("could not verify teleported content: {}", reason))
+}
+
+impl BDO {
+    /// Makes `verify_teleport` fail unless the signed message carries the
+    /// content's `teleport_content_hash`. Only turn this on for teleporters
+    /// that sign their content that way.
+    pub fn with_teleport_content_hashes(mut self, teleport_content_hashes: bool) -> Self {
+        self.teleport_content_hashes = teleport_content_hashes;
+        self
+    }
+
+    pub fn requires_teleport_content_hashes(&self) -> bool {
+        self.teleport_content_hashes
+    }
+
+    /// Checks a teleport's signature without trusting the server's `valid`
+    /// flag, and that it was signed by `expected_pub_key`.
+    pub fn verify_teleport(&self, response: TeleportResponse, expected_pub_key: &str) -> Result<VerifiedTeleport, BdoError> {
+        let (Some(html), Some(message), Some(signature), Some(pub_key)) =
+            (&response.html, &response.message, &response.signature, &response.teleporter_pub_key) else {
+            return Err(unverified("the response has no signed content"));
+        };
+
+        let expected = PublicKey::from_hex(expected_pub_key).map_err(|_| unverified("the expected pubKey isn't a public key"))?;
+        let teleporter = PublicKey::from_hex(pub_key).map_err(|_| unverified("teleporterPubKey isn't a public key"))?;
+        if teleporter != expected {
+            return Err(unverified("it was signed by a different pubKey"));
+        }
+
+        let signature = Signature::from_hex(signature).map_err(|_| unverified("the signature is malformed"))?;
+        self.sessionless.verify(message, &teleporter, &signature)
+            .map_err(|_| unverified("the signature doesn't match"))?;
+
+        // Without the hash, a server could keep a genuine message and
+        // signature and put whatever content it likes next to them.
+        let content_signed = message.to_ascii_lowercase().contains(&teleport_content_hash(html)?);
+        if self.teleport_content_hashes && !content_signed {
+            return Err(unverified("the signed message doesn't include the content's hash"));
+        }
+
+        let content = parse_teleport(html)?;
+        if content.attribute("pubKey").is_some_and(|tag_key| !tag_key.eq_ignore_ascii_case(pub_key))
+            || content.attribute("signature").is_some_and(|tag_signature| !tag_signature.eq_ignore_ascii_case(response.signature.as_deref().unwrap_or_default()))
+            || content.attribute("message").is_some_and(|tag_message| tag_message != message) {
+            return Err(unverified("the teleport tag doesn't match the signed message"));
+        }
+
+        Ok(VerifiedTeleport { response, content, content_signed })
+    }
+
+    /// `teleport`, verified against the `pubKey` parameter of `url`.
+    pub async fn teleport_verified(&self, uuid: &str, hash: &str, url: &str) -> Result<VerifiedTeleport, BdoError> {
+        let expected = expected_pub_key(url)
+            .ok_or_else(|| unverified("the url has no pubKey parameter to check against"))?;
+        let response = self.teleport(uuid, hash, url).await?;
+
+        self.verify_teleport(response, &expected)
+    }
+}
+
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr",
+];
+
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+/// Parses an HTML fragment into a tree under a nameless root element.
+fn parse_html(html: &str) -> TeleportElement {
+    let mut stack = vec![TeleportElement::default()];
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            push_text(&mut stack, rest);
+            break;
+        };
+        push_text(&mut stack, &rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+        } else if let Some(closing) = rest.strip_prefix("</") {
+            let end = closing.find('>').unwrap_or(closing.len());
+            close(&mut stack, closing[..end].trim());
+            rest = closing.get(end + 1..).unwrap_or("");
+        } else if rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            let (element, self_closing, after) = parse_tag(&rest[1..]);
+            rest = after;
+
+            if RAW_TEXT_ELEMENTS.contains(&element.name.as_str()) {
+                let closing = format!("</{}", element.name);
+                rest = rest.to_ascii_lowercase().find(&closing)
+                    .and_then(|end| rest[end..].find('>').map(|close| &rest[end + close + 1..]))
+                    .unwrap_or("");
+                attach(&mut stack, element);
+            } else if self_closing || VOID_ELEMENTS.contains(&element.name.as_str()) {
+                attach(&mut stack, element);
+            } else {
+                stack.push(element);
+            }
+        } else {
+            // A `<` that doesn't start a tag is just text.
+            push_text(&mut stack, "<");
+            rest = &rest[1..];
+        }
+    }
+
+    while stack.len() > 1 {
+        let element = stack.pop().expect("stack has an open element");
+        attach(&mut stack, element);
+    }
+    stack.pop().expect("stack has the root")
+}
+
+/// Parses a tag after its `<`, returning the element, whether it closed
+/// itself, and what follows the tag.
+fn parse_tag(tag: &str) -> (TeleportElement, bool, &str) {
+    let name_end = tag.find(|c: char| c.is_whitespace() || c == '>' || c == '/').unwrap_or(tag.len());
+    let name = tag[..name_end].to_ascii_lowercase();
+    let mut rest = &tag[name_end..];
+    let mut attributes = IndexMap::new();
+
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return (TeleportElement::new(&name, attributes), false, rest);
+        }
+        if let Some(after) = rest.strip_prefix("/>") {
+            return (TeleportElement::new(&name, attributes), true, after);
+        }
+        if let Some(after) = rest.strip_prefix('>') {
+            return (TeleportElement::new(&name, attributes), false, after);
+        }
+        if let Some(after) = rest.strip_prefix('/') {
+            rest = after;
+            continue;
+        }
+
+        let key_end = rest.find(|c: char| c.is_whitespace() || c == '=' || c == '>' || c == '/').unwrap_or(rest.len());
+        let key = rest[..key_end].to_string();
+        rest = rest[key_end..].trim_start();
+
+        let value = match rest.strip_prefix('=') {
+            Some(after) => {
+                let after = after.trim_start();
+                let (value, remaining) = match after.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let quoted = &after[1..];
+                        let end = quoted.find(quote).unwrap_or(quoted.len());
+                        (&quoted[..end], quoted.get(end + 1..).unwrap_or(""))
+                    },
+                    _ => {
+                        let end = after.find(|c: char| c.is_whitespace() || c == '>').unwrap_or(after.len());
+                        (&after[..end], &after[end..])
+                    },
+                };
+                rest = remaining;
+                decode_entities(value)
+            },
+            None => String::new(),
+        };
+        attributes.insert(key, value);
+    }
+}
+
+fn push_text(stack: &mut [TeleportElement], text: &str) {
+    let text = decode_entities(text.trim());
+    if text.is_empty() {
+        return;
+    }
+
+    let current = stack.last_mut().expect("stack has the root");
+    if !current.text.is_empty() {
+        current.text.push(' ');
+    }
+    current.text.push_str(&text);
+}
+
+fn attach(stack: &mut [TeleportElement], element: TeleportElement) {
+    stack.last_mut().expect("stack has the root").children.push(element);
+}
+
+/// Closes the innermost open element called `name`, and any left open inside
+/// it. Closing tags that match nothing are ignored.
+fn close(stack: &mut Vec<TeleportElement>, name: &str) {
+    let Some(index) = stack.iter().skip(1).rposition(|element| element.name.eq_ignore_ascii_case(name)) else {
+        return;
+    };
+
+    while stack.len() > index + 1 {
+        let element = stack.pop().expect("stack has an open element");
+        attach(stack, element);
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest.find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| decode_entity(&rest[1..end]).map(|c| (c, end)));
+        match entity {
+            Some((c, end)) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    decoded.push_str(rest);
+
+    decoded
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        _ => {
+            let code = entity.strip_prefix('#')?;
+            let code = match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+            char::from_u32(code)
+        },
+    }
+}
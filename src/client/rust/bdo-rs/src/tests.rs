@@ -2,28 +2,28 @@ use crate::{Bases, BDOUser, BDO, Spellbook, SuccessResult, EmojicodeResponse};
 use sessionless::hex::IntoHex;
 use sessionless::hex::FromHex;
 use sessionless::{Sessionless, PrivateKey};
-use std::collections::HashMap;
 use serde_json::json;
 use serde_json::Value;
 
 #[actix_rt::test]
 async fn test_bdo() {
+    let server = crate::fake::FakeBdoServer::start().expect("fake server");
 
-    let mut saved_user: BDOUser;
-    let mut saved_user2: BDOUser; 
-    let bdo = BDO::new(Some("http://localhost:3003/".to_string()), None);
-    let bdo2 = BDO::new(Some("http://localhost:3003/".to_string()), None);
-    let bdo3 = BDO::new(Some("http://localhost:3003/".to_string()), Some(Sessionless::from_private_key(PrivateKey::from_hex("a29435a4fb1a27a284a60b3409efeebbe6a64db606ff38aeead579ccf2262dc4").expect("private key"))));
+    let bdo = BDO::new(Some(server.url().to_string()), None);
+    let bdo2 = BDO::new(Some(server.url().to_string()), None);
+    let _bdo3 = BDO::new(Some(server.url().to_string()), Some(Sessionless::from_private_key(PrivateKey::from_hex("a29435a4fb1a27a284a60b3409efeebbe6a64db606ff38aeead579ccf2262dc4").expect("private key"))));
     let hash = "hereisanexampleofahash";
     let hash2 = "hereisasecondhash";
 
     async fn create_user(bdo: &BDO, hash: &str) -> Option<BDOUser> {
     println!("creating user");
-        let publicBDO = json!({
+        // The server locks a BDO whose `pub` is set to its `pubKey`.
+        let public_bdo = json!({
             "foo": "foo",
-            "pub": bdo.sessionless.public_key().to_hex()
+            "pub": bdo.sessionless.public_key().to_hex(),
+            "pubKey": bdo.sessionless.public_key().to_hex()
          });
-	let result = bdo.create_user(&hash, &publicBDO, false).await;
+	let result = bdo.create_user(hash, &public_bdo, &false).await;
     println!("got to here");
 
 	match result {
@@ -45,10 +45,10 @@ async fn test_bdo() {
 
     async fn create_user2_with_private_bdo(bdo: &BDO, hash: &str) -> Option<BDOUser> {
     println!("creating user2");
-        let privateBDO = json!({
+        let private_bdo = json!({
             "bar": "bar"
          });
-	let result = bdo.create_user(&hash, &privateBDO, false).await;
+	let result = bdo.create_user(hash, &private_bdo, &false).await;
     println!("got to here");
 
 	match result {
@@ -71,9 +71,10 @@ async fn test_bdo() {
     async fn update_bdo(bdo: &BDO, saved_user: &BDOUser, hash: &str) -> Option<BDOUser> {
         let update = json!({
             "foo": "bop",
-            "pub": bdo.sessionless.public_key().to_hex()
+            "pub": bdo.sessionless.public_key().to_hex(),
+            "pubKey": bdo.sessionless.public_key().to_hex()
          });
-        let result = bdo.update_bdo(&saved_user.uuid, &hash, &update, &true).await;
+        let result = bdo.update_bdo(&saved_user.uuid, hash, &update, &true).await;
         
         match result {
             Ok(user) => {
@@ -93,7 +94,7 @@ async fn test_bdo() {
     }
 
     async fn get_bdo(bdo: &BDO, bdo2: &BDO, saved_user: &BDOUser, hash: &str) -> Option<BDOUser> {
-        let result = bdo2.get_public_bdo(&saved_user.uuid, &hash, &bdo.sessionless.public_key().to_hex()).await;
+        let result = bdo2.get_public_bdo(&saved_user.uuid, hash, &bdo.sessionless.public_key().to_hex()).await;
 
         match result {
             Ok(user) => {
@@ -114,14 +115,10 @@ async fn test_bdo() {
 
     async fn get_bdo_by_emojicode(bdo: &BDO, pub_key: &str) -> Option<EmojicodeResponse> {
         // First, get the emojicode for the public BDO
-        let url = format!("http://localhost:3003/pubkey/{}/emojicode", pub_key);
-        let client = reqwest::Client::new();
-        let emojicode_info_result = client.get(&url).send().await;
-
-        let emojicode = match emojicode_info_result {
+        let emojicode = match bdo.get_emojicode_for_pub_key(pub_key).await {
             Ok(response) => {
-                let json: serde_json::Value = response.json().await.expect("json");
-                json["emojicode"].as_str().expect("emojicode string").to_string()
+                assert_eq!(response.pub_key, pub_key);
+                response.emojicode
             },
             Err(error) => {
                 eprintln!("Error getting emojicode: {}", error);
@@ -149,12 +146,12 @@ async fn test_bdo() {
         }
     }
 
-    async fn get_bases(bdo: &BDO, saved_user: &BDOUser, hash: &str) -> Option<Value> {
-        let result = bdo.get_bases(&saved_user.uuid, &hash).await;
+    async fn get_bases(bdo: &BDO, saved_user: &BDOUser, hash: &str) -> Option<Bases> {
+        let result = bdo.get_bases(&saved_user.uuid, hash).await;
     
         match result {
             Ok(bases) => {
-                println!("Successfully got bases: {}", bases);
+                println!("Successfully got bases: {:?}", bases);
                 assert_eq!(
                     true,
                     true
@@ -169,12 +166,12 @@ async fn test_bdo() {
         }
     }
 
-    async fn put_bases(bdo: &BDO, saved_user: &BDOUser, hash: &str, bases: &Bases) -> Option<Value> {
-        let result = bdo.save_bases(&saved_user.uuid, &hash, &bases).await;
+    async fn put_bases(bdo: &BDO, saved_user: &BDOUser, hash: &str, bases: &Bases) -> Option<Bases> {
+        let result = bdo.save_bases(&saved_user.uuid, hash, bases).await;
 
         match result {
             Ok(bases) => {
-                println!("Successfully got bases: {}", bases);
+                println!("Successfully got bases: {:?}", bases);
                 assert_eq!(
                     true,
                     true
@@ -190,7 +187,7 @@ async fn test_bdo() {
     }
 
     async fn get_spellbooks(bdo: &BDO, saved_user: &BDOUser, hash: &str) -> Option<Vec<Spellbook>> {
-        let result = bdo.get_spellbooks(&saved_user.uuid, &hash).await;
+        let result = bdo.get_spellbooks(&saved_user.uuid, hash).await;
     
         match result {
             Ok(spellbooks) => {
@@ -209,17 +206,17 @@ async fn test_bdo() {
         }
     }
 
-/*    async fn put_spellbook(bdo: &BDO, saved_user: &BDOUser, hash: &str, spellbook: &Spellbook) -> Option<Vec<Spellbook>> {
-        let result = bdo.update_bdo(&saved_user.uuid, &hash, &update, &is_public).await;
+    async fn put_spellbook(bdo: &BDO, saved_user: &BDOUser, hash: &str, spellbook: &Spellbook) -> Option<Vec<Spellbook>> {
+        let result = bdo.put_spellbook(&saved_user.uuid, hash, spellbook).await;
 
         match result {
-            Ok(user) => {
-                println!("Successfully got BDOUser: {}", user.uuid);
+            Ok(spellbooks) => {
+                println!("Successfully put spellbook: {}", spellbook.spellbookName);
                 assert_eq!(
-                    user.uuid.len(),
-                    36
+                    spellbooks.last().map(|saved| &saved.spellbookName),
+                    Some(&spellbook.spellbookName)
                 );
-                Some(user)
+                Some(spellbooks)
             },
             Err(error) => {
                 eprintln!("Error occurred put_spellbook: {}", error);
@@ -228,17 +225,13 @@ async fn test_bdo() {
             }
         }
     }
-*/
 
     async fn delete_user(bdo: &BDO, saved_user: &BDOUser, hash: &str) -> Option<SuccessResult> {
-        let result = bdo.delete_user(&saved_user.uuid, &hash).await;
+        let result = bdo.delete_user(&saved_user.uuid, hash).await;
 
         match result {
             Ok(success) => {
-                assert_eq!(
-                    success.success,
-                    true
-                );
+                assert!(success.success);
                 Some(success)
             }
             Err(error) => {
@@ -249,15 +242,21 @@ async fn test_bdo() {
         }
     }
         
-    saved_user = create_user(&bdo, &hash).await.expect("user");
-    saved_user2 = create_user2_with_private_bdo(&bdo2, &hash2).await.expect("user2");
+    let saved_user: BDOUser = create_user(&bdo, hash).await.expect("user");
+    let saved_user2: BDOUser = create_user2_with_private_bdo(&bdo2, hash2).await.expect("user2");
 
     // Update user to make it public so it gets an emojicode
-    let _ = update_bdo(&bdo, &saved_user, &hash).await.expect("update_bdo");
+    let _ = update_bdo(&bdo, &saved_user, hash).await.expect("update_bdo");
 
-    Some(get_bdo(&bdo, &bdo2, &saved_user2, &hash2).await.expect("get_bdo"));
-    Some(get_bdo_by_emojicode(&bdo2, &bdo.sessionless.public_key().to_hex()).await.expect("get_bdo_by_emojicode"));
-    Some(get_spellbooks(&bdo, &saved_user, &hash).await);
+    get_bdo(&bdo, &bdo2, &saved_user2, hash2).await.expect("get_bdo");
+    get_bdo_by_emojicode(&bdo2, &bdo.sessionless.public_key().to_hex()).await.expect("get_bdo_by_emojicode");
+    let mut bases = Bases::default();
+    bases.add_base("allyabase", crate::BaseConfig::new("Allyabase", "https://allyabase.com").with_services(&["bdo"]));
+    put_bases(&bdo, &saved_user, hash, &bases).await.expect("put_bases");
+    assert_eq!(get_bases(&bdo, &saved_user, hash).await.expect("get_bases"), bases);
+    put_spellbook(&bdo, &saved_user, hash, &Spellbook { spellbookName: "allyabase".to_string(), ..Spellbook::default() }).await.expect("put_spellbook");
+    get_spellbooks(&bdo, &saved_user, hash).await.expect("get_spellbooks");
+    delete_user(&bdo2, &saved_user2, hash2).await.expect("delete_user");
 
 /*    if let Some(ref user) = saved_user {
 	Some(update_bdo(&bdo, user, &hash).await.expect("update_bdo"));
@@ -285,3 +284,1140 @@ async fn test_bdo() {
     }*/
 
 }
+
+#[test]
+fn test_error_classification() {
+    use crate::BdoError;
+    use reqwest::StatusCode;
+
+    // The node server answers auth failures with a 200 and an error body.
+    let auth = BdoError::from_response(StatusCode::OK, r#"{"error":"Auth error"}"#);
+    assert!(matches!(auth, Some(BdoError::Auth { .. })));
+
+    let not_found = BdoError::from_response(StatusCode::NOT_FOUND, r#"{"error":"Emojicode not found"}"#);
+    assert!(matches!(not_found, Some(BdoError::NotFound { .. })));
+
+    let conflict = BdoError::from_response(StatusCode::OK, r#"{"success":false,"error":"Cannot overwrite public BDO with different pubKey"}"#);
+    assert!(matches!(conflict, Some(BdoError::PubKeyConflict { .. })));
+
+    let not_acceptable = BdoError::from_response(StatusCode::NOT_ACCEPTABLE, r#"{"code":"406","message":"Not acceptable"}"#);
+    match not_acceptable {
+        Some(BdoError::Status { status, body }) => {
+            assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+            assert_eq!(body.code.as_deref(), Some("406"));
+        },
+        other => panic!("expected a status error, got {:?}", other)
+    }
+
+    let bad_gateway = BdoError::from_response(StatusCode::BAD_GATEWAY, "<html>nope</html>");
+    assert_eq!(bad_gateway.and_then(|err| err.status()), Some(StatusCode::BAD_GATEWAY));
+
+    assert!(BdoError::from_response(StatusCode::OK, r#"{"uuid":"abc","bdo":{}}"#).is_none());
+}
+
+#[test]
+fn test_template_keeps_bdo_fields() {
+    use crate::structs::Templates;
+
+    let templates: Templates = serde_json::from_value(json!({
+        "success": true,
+        "hash": "my_app",
+        "templates": [{
+            "emojicode": "🌍🔑💎🌟💎🎨🐉📌",
+            "pubKey": "02abc",
+            "name": "Starter",
+            "layout": { "columns": 2 }
+        }],
+        "count": 1
+    })).expect("templates");
+
+    let template = &templates.templates[0];
+    assert_eq!(template.pub_key, "02abc");
+    assert_eq!(template.bdo["name"], "Starter");
+    assert_eq!(template.bdo["layout"]["columns"], 2);
+}
+
+#[actix_rt::test]
+async fn test_get_bdo_by_short_code() {
+    use crate::BdoError;
+
+    let (url, requests) = stand_in_server_with(vec![
+        (200, r#"{"shortCode":"a1b2c3d4e","pubKey":"02abc","bdo":{"foo":"bar"}}"#),
+        (404, r#"{"error":"Short code not found"}"#),
+        (404, r#"{"error":"BDO not found"}"#),
+    ]);
+    let bdo = BDO::new(Some(url), None);
+
+    let response = bdo.get_bdo_by_short_code("a1b2c3d4e").await.expect("short code lookup");
+    assert_eq!(response.short_code, "a1b2c3d4e");
+    assert_eq!(response.pub_key, "02abc");
+    assert_eq!(response.bdo, json!({ "foo": "bar" }));
+    let (request_line, _) = requests.recv().expect("request");
+    assert!(request_line.starts_with("GET /short/a1b2c3d4e "));
+
+    match bdo.get_bdo_by_short_code("missing").await {
+        Err(BdoError::NotFound { status, message }) => {
+            assert_eq!(status.as_u16(), 404);
+            assert_eq!(message, "Short code not found");
+        },
+        other => panic!("expected not found, got {:?}", other),
+    }
+    assert!(matches!(bdo.get_bdo_by_short_code("orphaned").await, Err(BdoError::NotFound { .. })));
+}
+
+#[test]
+fn test_build_spell_signs_with_caster() {
+    use crate::{BdoError, Caster};
+    use sessionless::Signature;
+
+    let bdo = BDO::new(None, None);
+    assert!(matches!(bdo.build_spell("bdoUserBases", json!({})), Err(BdoError::Config(_))));
+
+    let bdo = bdo.with_caster(Caster::new("caster-uuid", 7));
+    let spell = bdo.build_spell("bdoUserBases", json!({ "hash": "hash" })).expect("spell");
+    let next = bdo.build_spell("bdoUserBases", json!({ "hash": "hash" })).expect("spell");
+
+    assert_eq!(spell.ordinal, 7);
+    assert_eq!(next.ordinal, 8);
+    assert_eq!(spell.message(), format!("{}bdoUserBases{}50true7", spell.timestamp, "caster-uuid"));
+
+    let signature = Signature::from_hex(&spell.caster_signature).expect("signature");
+    assert!(bdo.sessionless.verify(spell.message(), bdo.sessionless.public_key(), &signature).is_ok());
+
+    let payload = serde_json::to_value(&spell).expect("payload");
+    assert_eq!(payload["casterUUID"], "caster-uuid");
+    assert_eq!(payload["totalCost"], 50);
+}
+
+/// Answers a single request with `response` and hands back the request line and JSON body.
+fn stand_in_server(response: &'static str) -> (String, std::sync::mpsc::Receiver<(String, Value)>) {
+    stand_in_server_with(vec![(200, response)])
+}
+
+/// Answers one request per `(status, body)` pair, in order.
+fn stand_in_server_with(responses: Vec<(u16, &'static str)>) -> (String, std::sync::mpsc::Receiver<(String, Value)>) {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let url = format!("http://{}/", listener.local_addr().expect("addr"));
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    std::thread::spawn(move || {
+        for (status, response) in responses {
+            let (mut stream, _) = listener.accept().expect("accept");
+            let mut reader = BufReader::new(stream.try_clone().expect("clone"));
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).expect("request line");
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).expect("header");
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().expect("content length");
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).expect("body");
+
+            let reply = format!("HTTP/1.1 {} Stand-In\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, response.len(), response);
+            stream.write_all(reply.as_bytes()).expect("reply");
+            let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+            sender.send((request_line.trim().to_string(), body)).expect("send");
+        }
+    });
+
+    (url, receiver)
+}
+
+#[actix_rt::test]
+async fn test_writes_route_through_resolver() {
+    use crate::Caster;
+
+    let (resolver_url, requests) = stand_in_server(r#"{"success":true,"uuid":"caster-uuid","bdo":{"foo":"bar"},"emojiShortcode":null}"#);
+    let bdo = BDO::new(Some("http://localhost:1/".to_string()), None)
+        .with_caster(Caster::new("caster-uuid", 3))
+        .with_resolver(resolver_url.trim_end_matches('/'));
+
+    let user = bdo.update_bdo("caster-uuid", "hash", &json!({ "foo": "bar" }), &false).await.expect("update via resolver");
+    assert_eq!(user.uuid, "caster-uuid");
+    assert_eq!(user.bdo["foo"], "bar");
+
+    let (request_line, spell) = requests.recv().expect("request");
+    assert!(request_line.starts_with("POST /resolve/bdoUserBdo "));
+    assert_eq!(spell["spell"], "bdoUserBdo");
+    assert_eq!(spell["ordinal"], 3);
+    assert_eq!(spell["components"]["uuid"], "caster-uuid");
+    assert_eq!(spell["components"]["bdo"]["foo"], "bar");
+}
+
+#[test]
+fn test_blocking_client() {
+    use crate::BlockingBDO;
+
+    let (url, requests) = stand_in_server(r#"{"emojicode":"🌍🔑💎🌟💎🎨🐉📌","pubKey":"02abc","bdo":{"foo":"bar"},"createdAt":1700000000000}"#);
+    let bdo = BlockingBDO::new(Some(url), None);
+
+    let response = bdo.get_bdo_by_emojicode("🌍🔑💎🌟💎🎨🐉📌").expect("blocking emojicode lookup");
+    assert_eq!(response.pub_key, "02abc");
+    assert_eq!(response.bdo["foo"], "bar");
+    assert_eq!(response.created_at, Some(1700000000000));
+
+    let (request_line, _) = requests.recv().expect("request");
+    assert!(request_line.starts_with("GET /emoji/"));
+}
+
+#[test]
+fn test_emojicode_without_creation_time() {
+    use crate::structs::EmojicodeResponse;
+
+    let response: EmojicodeResponse = serde_json::from_str(r#"{"emojicode":"🌟","pubKey":"02abc","bdo":{"foo":"bar"},"createdAt":null}"#).expect("decode");
+    assert_eq!(response.created_at, None);
+    assert_eq!(response.bdo["foo"], "bar");
+}
+
+#[test]
+fn test_trace_redaction() {
+    use crate::trace::{redact_url, redact_value, REDACTED};
+
+    let url = redact_url("http://localhost:3003/user/abc/bdo?timestamp=1&hash=secret&signature=deadbeef&pubKey=02abc");
+    assert_eq!(url, format!("http://localhost:3003/user/abc/bdo?timestamp=1&hash={}&signature={}&pubKey=02abc", REDACTED, REDACTED));
+
+    let payload = redact_value(&json!({
+        "uuid": "abc",
+        "hash": "secret",
+        "signature": "deadbeef",
+        "spell": { "casterSignature": "cafe", "components": [{ "hash": "secret" }] }
+    }));
+    assert_eq!(payload["uuid"], "abc");
+    assert_eq!(payload["hash"], REDACTED);
+    assert_eq!(payload["signature"], REDACTED);
+    assert_eq!(payload["spell"]["casterSignature"], REDACTED);
+    assert_eq!(payload["spell"]["components"][0]["hash"], REDACTED);
+}
+
+#[test]
+fn test_builder_joins_urls() {
+    use crate::BdoError;
+    use std::time::Duration;
+
+    let bdo = BDO::builder()
+        .base_url("https://wiki.example.com")
+        .path_prefix("/plugin/allyabase/bdo")
+        .timeout(Duration::from_secs(5))
+        .user_agent("bdo-rs-tests")
+        .build()
+        .expect("builder");
+    assert_eq!(bdo.base_url(), "https://wiki.example.com/plugin/allyabase/bdo/");
+
+    let bdo = BDO::builder().base_url("http://localhost:3003").build().expect("builder");
+    assert_eq!(bdo.base_url(), "http://localhost:3003/");
+
+    assert_eq!(BDO::new(Some("http://localhost:3003".to_string()), None).base_url(), "http://localhost:3003/");
+
+    assert!(matches!(BDO::builder().base_url("localhost:3003").build(), Err(BdoError::Config(_))));
+    assert!(matches!(BDO::builder().base_url("http://localhost:3003/?foo=bar").build(), Err(BdoError::Config(_))));
+    assert!(matches!(
+        BDO::builder().client(reqwest::Client::new()).timeout(Duration::from_secs(5)).build(),
+        Err(BdoError::Config(_))
+    ));
+}
+
+#[actix_rt::test]
+async fn test_reads_retry_transient_failures() {
+    use crate::{BdoError, RetryPolicy};
+    use std::time::Duration;
+
+    let policy = RetryPolicy {
+        initial_backoff: Duration::from_millis(1),
+        ..RetryPolicy::default()
+    };
+
+    let (url, requests) = stand_in_server_with(vec![
+        (503, r#"{"code":"503","message":"Service Unavailable"}"#),
+        (200, r#"{"uuid":"abc","bdo":{"foo":"bar"}}"#),
+    ]);
+    let bdo = BDO::new(Some(url), None).with_retry_policy(policy.clone());
+
+    let user = bdo.get_bdo("abc", "hash").await.expect("retried read");
+    assert_eq!(user.bdo["foo"], "bar");
+
+    // Each attempt is signed with its own timestamp.
+    let (first, _) = requests.recv().expect("first attempt");
+    let (second, _) = requests.recv().expect("second attempt");
+    assert!(first.starts_with("GET /user/abc/bdo?"));
+    assert!(second.starts_with("GET /user/abc/bdo?"));
+
+    // Writes only retry when asked to.
+    let (url, _requests) = stand_in_server_with(vec![
+        (503, r#"{"code":"503","message":"Service Unavailable"}"#),
+    ]);
+    let bdo = BDO::new(Some(url), None).with_retry_policy(policy.clone());
+    let result = bdo.update_bdo("abc", "hash", &json!({}), &false).await;
+    assert!(matches!(result, Err(BdoError::Status { .. })));
+
+    // A body cut off mid-read is retried too.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let url = format!("http://{}/", listener.local_addr().expect("addr"));
+    std::thread::spawn(move || {
+        use std::io::{Read, Write};
+
+        for reply in [
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 100\r\nConnection: close\r\n\r\n{\"uuid\"",
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 28\r\nConnection: close\r\n\r\n{\"uuid\":\"abc\",\"bdo\":{\"a\":1}}",
+        ] {
+            let (mut stream, _) = listener.accept().expect("accept");
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request).expect("request");
+            stream.write_all(reply.as_bytes()).expect("reply");
+        }
+    });
+    let bdo = BDO::new(Some(url), None).with_retry_policy(policy);
+    assert_eq!(bdo.get_bdo("abc", "hash").await.expect("retried read").bdo, json!({ "a": 1 }));
+}
+
+#[actix_rt::test]
+async fn test_session_remembers_identity() {
+    let (url, requests) = stand_in_server_with(vec![
+        (200, r#"{"uuid":"abc","bdo":{"foo":"bar"},"emojiShortcode":null}"#),
+        (200, r#"{"bases":{"allyabase":{"name":"Allyabase"}}}"#),
+    ]);
+    let bdo = BDO::new(Some(url), None);
+
+    let session = bdo.create_user_session("my_app", &json!({ "foo": "bar" }), &false).await.expect("session");
+    assert_eq!(session.uuid(), "abc");
+    assert_eq!(session.hash(), "my_app");
+
+    let bases = session.get_bases().await.expect("bases");
+    assert_eq!(bases.base("allyabase").expect("allyabase").name, "Allyabase");
+
+    let (create, payload) = requests.recv().expect("create");
+    assert!(create.starts_with("PUT /user/create "));
+    assert_eq!(payload["hash"], "my_app");
+    let (get_bases, _) = requests.recv().expect("get bases");
+    assert!(get_bases.starts_with("GET /user/abc/bases?"));
+    assert!(get_bases.contains("hash=my_app"));
+}
+
+#[test]
+fn test_file_key_store_round_trip() {
+    use crate::{BdoError, FileKeyStore, Identity, KeyStore};
+
+    let dir = std::env::temp_dir().join(format!("bdo-rs-keys-{}", std::process::id()));
+    let store = FileKeyStore::new(&dir, "correct horse battery staple");
+    let sessionless = Sessionless::new();
+    let identity = Identity { uuid: "abc".to_string(), private_key: *sessionless.private_key() };
+
+    assert!(store.load("my app").expect("empty load").is_none());
+    store.save("my app", &identity).expect("save");
+
+    let contents = std::fs::read_to_string(store.path_for("my app")).expect("key file");
+    assert!(!contents.contains(&sessionless.private_key().to_hex()));
+
+    // Saving again over a world-readable leftover temp file still leaves only owner-readable files.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let path = store.path_for("my app");
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, "leftover").expect("leftover");
+        std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o644)).expect("chmod");
+        store.save("my app", &identity).expect("save again");
+        assert_eq!(std::fs::metadata(&path).expect("key file").permissions().mode() & 0o777, 0o600);
+        assert!(!tmp.exists());
+    }
+
+    let loaded = store.load("my app").expect("load").expect("identity");
+    assert_eq!(loaded.uuid, "abc");
+    assert_eq!(loaded.private_key, *sessionless.private_key());
+
+    let wrong = FileKeyStore::new(&dir, "wrong passphrase");
+    assert!(matches!(wrong.load("my app"), Err(BdoError::KeyStore(_))));
+
+    store.remove("my app").expect("remove");
+    assert!(store.load("my app").expect("load after remove").is_none());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[actix_rt::test]
+async fn test_ensure_user_reuses_stored_identity() {
+    use crate::{KeyStore, MemoryKeyStore};
+
+    let (url, requests) = stand_in_server(r#"{"uuid":"abc","bdo":{},"emojiShortcode":null}"#);
+    let store = MemoryKeyStore::new();
+
+    let mut bdo = BDO::new(Some(url.clone()), None);
+    assert_eq!(bdo.check_for_user(&store, "my_app").expect("check"), None);
+    let uuid = bdo.ensure_user(&store, "my_app", &json!({}), &false).await.expect("create");
+    assert_eq!(uuid, "abc");
+    requests.recv().expect("create request");
+
+    // A fresh client picks the saved keys back up without calling the server.
+    let mut reloaded = BDO::new(Some(url), None);
+    let uuid = reloaded.ensure_user(&store, "my_app", &json!({}), &false).await.expect("reload");
+    assert_eq!(uuid, "abc");
+    assert_eq!(reloaded.sessionless.public_key(), bdo.sessionless.public_key());
+    assert_eq!(store.load("my_app").expect("load").expect("identity").uuid, "abc");
+}
+
+#[actix_rt::test]
+async fn test_typed_bdo_round_trip() {
+    use crate::BdoError;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Level {
+        name: String,
+        enemies: u32,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct GameConfig {
+        title: String,
+        levels: Vec<Level>,
+    }
+
+    let (url, requests) = stand_in_server_with(vec![
+        (200, r#"{"uuid":"abc","bdo":{"title":"Dungeon","levels":[{"name":"Cellar","enemies":3}]}}"#),
+        (200, r#"{"uuid":"abc","bdo":{"title":"Dungeon","levels":[{"name":"Cellar","enemies":"lots"}]}}"#),
+    ]);
+    let bdo = BDO::new(Some(url), None);
+    let config = GameConfig {
+        title: "Dungeon".to_string(),
+        levels: vec![Level { name: "Cellar".to_string(), enemies: 3 }],
+    };
+
+    let saved = bdo.update_bdo_typed("abc", "my_app", &config, &false).await.expect("update");
+    assert_eq!(saved.bdo, config);
+    let (_, payload) = requests.recv().expect("update");
+    assert_eq!(payload["bdo"]["levels"][0]["enemies"], 3);
+
+    match bdo.get_bdo_as::<GameConfig>("abc", "my_app").await {
+        Err(BdoError::Typed { path, .. }) => assert_eq!(path, "levels[0].enemies"),
+        other => panic!("expected a typed error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_spellbook_round_trip() {
+    use crate::spellbook::{Destination, Spell};
+
+    let raw = r#"{
+        "spellbookName": "allyabase",
+        "joinup": {
+            "cost": 400,
+            "destinations": [
+                { "stopName": "fount", "stopURL": "http://localhost:3006/magic/spell/" },
+                { "stopName": "bdo", "stopURL": "http://localhost:3003/magic/spell/", "note": "kept" }
+            ],
+            "resolver": "fount",
+            "mp": true,
+            "nineum": 2
+        },
+        "bdoUserCreate": { "cost": 50, "resolver": "fount", "mp": true },
+        "free": { "resolver": "fount" },
+        "version": 2,
+        "notes": ["not", "a", "spell"],
+        "broken": { "cost": "lots" }
+    }"#;
+
+    let mut spellbook: Spellbook = serde_json::from_str(raw).expect("spellbook");
+    assert_eq!(spellbook.name(), "allyabase");
+    assert_eq!(spellbook.spells.keys().collect::<Vec<_>>(), vec!["joinup", "bdoUserCreate", "free"]);
+    assert_eq!(spellbook.extra.keys().collect::<Vec<_>>(), vec!["broken", "notes", "version"]);
+    let joinup = spellbook.spell("joinup").expect("joinup");
+    assert_eq!(joinup.cost, 400);
+    assert_eq!(joinup.destination("bdo").expect("bdo").stop_url, "http://localhost:3003/magic/spell/");
+    assert_eq!(
+        serde_json::to_value(&spellbook).expect("serialize"),
+        serde_json::from_str::<Value>(raw).expect("raw")
+    );
+
+    let spell = Spell::new(50, vec![Destination::new("bdo", "http://localhost:3003/magic/spell/")]).with_mp(true);
+    assert!(!spellbook.add_spell("joinup", spell.clone()));
+    assert!(!spellbook.add_spell("spellbookName", spell.clone()));
+    assert!(!spellbook.add_spell("version", spell.clone()));
+    assert!(spellbook.add_spell("bdoUserBdo", spell.clone()));
+    assert_eq!(spellbook.replace_spell("joinup", spell.clone()).expect("replaced").cost, 400);
+    assert_eq!(spellbook.remove_spell("bdoUserCreate").expect("removed").cost, 50);
+    assert_eq!(spellbook.spells.keys().collect::<Vec<_>>(), vec!["joinup", "free", "bdoUserBdo"]);
+    assert!(spellbook.replace_spell("broken", spell).is_none());
+    assert!(!spellbook.extra.contains_key("broken"));
+}
+
+#[actix_rt::test]
+async fn test_bases_are_typed() {
+    use crate::BaseConfig;
+
+    let (url, requests) = stand_in_server(r#"{"bases":{"allyabase":{"name":"Allyabase","url":"https://allyabase.com","services":["bdo","fount"],"region":"eu"},"mybase":{"name":"My Base","url":"https://mybase.com","services":["bdo"]}}}"#);
+    let bdo = BDO::new(Some(url), None);
+
+    let mut bases = Bases::default();
+    bases.add_base("allyabase", BaseConfig::new("Allyabase", "https://allyabase.com").with_services(&["bdo", "fount"]));
+    bases.add_base("mybase", BaseConfig::new("My Base", "https://mybase.com").with_services(&["bdo"]));
+    let saved = bdo.save_bases("abc", "my_app", &bases).await.expect("save");
+
+    let (_, payload) = requests.recv().expect("save");
+    assert_eq!(payload["bases"]["allyabase"]["services"], json!(["bdo", "fount"]));
+    assert!(payload["bases"].get("bases").is_none());
+
+    assert_eq!(saved.base("allyabase").expect("allyabase").extra["region"], "eu");
+    assert_eq!(saved.running("bdo").map(|(id, _)| id).collect::<Vec<_>>(), vec!["allyabase", "mybase"]);
+    assert_eq!(saved.running("fount").map(|(id, _)| id).collect::<Vec<_>>(), vec!["allyabase"]);
+
+    let mut saved = saved;
+    assert_eq!(saved.remove_base("mybase").expect("removed").name, "My Base");
+    assert!(saved.base("mybase").is_none());
+
+    let partial: BaseConfig = serde_json::from_value(json!({"url": "https://partial.com"})).expect("partial");
+    assert_eq!(serde_json::to_value(&partial).expect("serialize"), json!({"url": "https://partial.com"}));
+}
+
+#[actix_rt::test]
+async fn test_teleport_content() {
+    use crate::teleport::parse_teleport;
+    use crate::BdoError;
+
+    let html = r#"<!DOCTYPE html><html><body>
+        <teleport pubKey="02abc" signature="sig" message="1700000000000:products:teleport">
+          <!-- products -->
+          <teleportal id="p1" category="ebook" price="1000">
+            <name>Rust &amp; You</name>
+            <description>A book<br>about Rust</description>
+            <img src='cover.png'>
+          </teleportal>
+          <teleportal id=p2 category="course" price="2500">
+            <name>Async &#x1F980;</name>
+            <description>Unclosed
+          </teleportal>
+        </teleport>
+    </body></html>"#;
+    let body = json!({
+        "valid": true,
+        "html": html,
+        "message": "1700000000000:products:teleport",
+        "signature": "sig",
+        "teleporterPubKey": "02abc",
+        "amount": null,
+        "spell": null,
+    }).to_string();
+    let (url, _requests) = stand_in_server(Box::leak(body.into_boxed_str()));
+    let bdo = BDO::new(Some(url), None);
+
+    let teleported = bdo.teleport("abc", "my_app", "allyabase://sanora/teleportable-products").await.expect("teleport");
+    assert_eq!(teleported.teleporter_pub_key.as_deref(), Some("02abc"));
+
+    let teleport = teleported.content().expect("content");
+    assert_eq!(teleport.attribute("pubkey"), Some("02abc"));
+    let products: Vec<_> = teleport.teleportals().collect();
+    assert_eq!(products.len(), 2);
+    assert_eq!(products[0].attribute("id"), Some("p1"));
+    assert_eq!(products[0].child_text("name"), Some("Rust & You"));
+    assert_eq!(products[0].child_text("description"), Some("A book about Rust"));
+    assert_eq!(products[0].child("img").and_then(|img| img.attribute("src")), Some("cover.png"));
+    assert_eq!(products[1].attribute("id"), Some("p2"));
+    assert_eq!(products[1].child_text("name"), Some("Async 🦀"));
+    assert_eq!(products[1].child_text("description"), Some("Unclosed"));
+
+    assert!(matches!(parse_teleport("<div>nothing here</div>"), Err(BdoError::Teleport(_))));
+}
+
+#[actix_rt::test]
+async fn test_teleport_verified_against_url_pub_key() {
+    use crate::structs::TeleportResponse;
+    use crate::teleport::teleport_content_hash;
+    use crate::BdoError;
+
+    // Signed the way teleporters sign today: `timestamp:content:teleport`.
+    let teleporter = Sessionless::new();
+    let pub_key = teleporter.public_key().to_hex();
+    let body = r#"<teleportal id="p1"><name>Book</name></teleportal>"#;
+    let message = "1700000000000:products:teleport";
+    let signature = teleporter.sign(message).to_hex();
+    let html = format!(r#"<teleport pubKey="{}" signature="{}" message="{}">{}</teleport>"#, pub_key, signature, message, body);
+    let response = TeleportResponse {
+        valid: true,
+        html: Some(html),
+        message: Some(message.to_string()),
+        signature: Some(signature.clone()),
+        teleporter_pub_key: Some(pub_key.clone()),
+        ..TeleportResponse::default()
+    };
+
+    let response_body = serde_json::to_string(&response).expect("body");
+    let (url, _requests) = stand_in_server(Box::leak(response_body.into_boxed_str()));
+    let bdo = BDO::new(Some(url), None);
+
+    let teleport_url = format!("allyabase://sanora/teleportable-products?pubKey={}", pub_key);
+    let verified = bdo.teleport_verified("abc", "my_app", &teleport_url).await.expect("verified");
+    assert_eq!(verified.teleporter_pub_key(), pub_key);
+    assert_eq!(verified.message(), message);
+    // The message doesn't cover the HTML, so it's only offered as unsigned.
+    assert!(!verified.is_content_signed());
+    assert!(verified.content().is_none());
+    assert_eq!(verified.unsigned_content().teleportals().next().and_then(|product| product.child_text("name")), Some("Book"));
+
+    // Signed by someone other than the pubKey in the url.
+    let impostor = Sessionless::new().public_key().to_hex();
+    assert!(matches!(bdo.verify_teleport(response.clone(), &impostor), Err(BdoError::Teleport(_))));
+
+    // A server claiming the expected pubKey signed a message it didn't.
+    let forged = TeleportResponse {
+        message: Some("1700000000000:other:teleport".to_string()),
+        ..response.clone()
+    };
+    assert!(matches!(bdo.verify_teleport(forged, &pub_key), Err(BdoError::Teleport(_))));
+
+    // The tag's attributes swapped under a genuine signature.
+    let swapped = TeleportResponse {
+        html: Some(format!(r#"<teleport pubKey="{}" signature="{}" message="something else">{}</teleport>"#, pub_key, signature, body)),
+        ..response.clone()
+    };
+    assert!(matches!(bdo.verify_teleport(swapped, &pub_key), Err(BdoError::Teleport(_))));
+
+    // Clients that require content hashes turn down teleports without one.
+    let strict = BDO::new(None, None).with_teleport_content_hashes(true);
+    assert!(matches!(strict.verify_teleport(response.clone(), &pub_key), Err(BdoError::Teleport(_))));
+
+    // A teleporter that puts the content's hash in its message vouches for the HTML.
+    let content_hash = teleport_content_hash(&format!("<teleport>{}</teleport>", body)).expect("hash");
+    let message = format!("1700000000000:{}:teleport", content_hash);
+    let signature = teleporter.sign(&message).to_hex();
+    let bound = TeleportResponse {
+        html: Some(format!(r#"<teleport pubKey="{}" signature="{}" message="{}">{}</teleport>"#, pub_key, signature, message, body)),
+        message: Some(message.clone()),
+        signature: Some(signature.clone()),
+        ..response
+    };
+    let verified = strict.verify_teleport(bound.clone(), &pub_key).expect("verified content");
+    assert_eq!(verified.content().and_then(|teleport| teleport.teleportals().next()).and_then(|product| product.child_text("name")), Some("Book"));
+
+    // The teleportals swapped, keeping the genuine message and signature.
+    let swapped = TeleportResponse {
+        html: Some(format!(r#"<teleport pubKey="{}" signature="{}" message="{}"><teleportal id="p1"><name>Forgery</name></teleportal></teleport>"#, pub_key, signature, message)),
+        ..bound
+    };
+    assert!(matches!(strict.verify_teleport(swapped.clone(), &pub_key), Err(BdoError::Teleport(_))));
+    assert!(bdo.verify_teleport(swapped, &pub_key).expect("verified message").content().is_none());
+
+    assert!(matches!(bdo.teleport_verified("abc", "my_app", "allyabase://sanora/teleportable-products").await, Err(BdoError::Teleport(_))));
+}
+
+#[test]
+fn test_allyabase_urls() {
+    use crate::allyabase::ServiceTable;
+    use crate::{AllyabaseUrl, BdoError};
+
+    let url: AllyabaseUrl = "allyabase://sanora/teleportable-products?pubKey=02abc".parse().expect("url");
+    assert_eq!(url.service(), "sanora");
+    assert_eq!(url.path(), "/teleportable-products");
+    assert_eq!(url.query(), Some("pubKey=02abc"));
+    assert_eq!(url.to_string(), "allyabase://sanora/teleportable-products?pubKey=02abc");
+    assert_eq!(url.translate().expect("translate"), "http://127.0.0.1:7243/teleportable-products?pubKey=02abc");
+
+    let built = AllyabaseUrl::new("addie", "charge").expect("built");
+    assert_eq!(built.to_string(), "allyabase://addie/charge");
+    assert_eq!(built.query(), None);
+
+    assert!(matches!(AllyabaseUrl::parse("https://bdo/"), Err(BdoError::Config(_))));
+    assert!(matches!(AllyabaseUrl::parse("allyabase://Bad_Name/"), Err(BdoError::Config(_))));
+    assert!(matches!(AllyabaseUrl::parse("allyabase:///path"), Err(BdoError::Config(_))));
+    assert!(matches!(AllyabaseUrl::parse("allyabase://unknown/").expect("url").translate(), Err(BdoError::Config(_))));
+
+    let table = ServiceTable::new().with_host("bdo.internal").with_port("bdo", 4003).with_port("unknown", 9000);
+    assert_eq!(AllyabaseUrl::parse("allyabase://unknown/x").expect("url").translate_with(&table).expect("translate"), "http://bdo.internal:9000/x");
+
+    assert_eq!(BDO::new(Some("allyabase://bdo".to_string()), None).base_url(), "http://127.0.0.1:3003/");
+    let bdo = BDO::builder()
+        .base_url("allyabase://bdo")
+        .service_table(table)
+        .resolver("allyabase://fount")
+        .build()
+        .expect("bdo");
+    assert_eq!(bdo.base_url(), "http://bdo.internal:4003/");
+    assert_eq!(bdo.resolver_url(), Some("http://bdo.internal:3006/"));
+    assert!(matches!(BDO::builder().base_url("allyabase://nope").build(), Err(BdoError::Config(_))));
+    assert_eq!(BDO::new(Some("allyabase://nope".to_string()), None).base_url(), "allyabase://nope/");
+}
+
+#[actix_rt::test]
+async fn test_signed_public_bdos() {
+    use crate::canonical::to_canonical_string;
+    use crate::envelope::ENVELOPE_KEY;
+    use crate::BdoError;
+
+    let parsed: Value = serde_json::from_str(r#"{"b":1,"a":[true,{"d":"x","c":null}]}"#).expect("json");
+    assert_eq!(to_canonical_string(&parsed), r#"{"a":[true,{"c":null,"d":"x"}],"b":1}"#);
+
+    let owner = BDO::new(None, None).with_signed_public_bdos(true);
+    let owner_pub_key = owner.sessionless.public_key().to_hex();
+    let data = json!({ "title": "Shop", "items": [1, 2, 3] });
+    let signed = owner.sign_bdo(&data).expect("signed");
+    let mut tampered = signed.clone();
+    tampered["title"] = json!("Scam");
+
+    let user = |bdo: &Value| -> &'static str {
+        Box::leak(json!({ "uuid": "abc", "bdo": bdo }).to_string().into_boxed_str())
+    };
+    let emojicode = |bdo: &Value| -> &'static str {
+        Box::leak(json!({ "emojicode": "🌟", "pubKey": owner_pub_key, "bdo": bdo, "createdAt": 0 }).to_string().into_boxed_str())
+    };
+    let (url, requests) = stand_in_server_with(vec![
+        (200, user(&signed)),
+        (200, user(&signed)),
+        (200, user(&signed)),
+        (200, emojicode(&tampered)),
+        (200, user(&data)),
+    ]);
+    let owner = BDO::builder().base_url(&url).sessionless(Sessionless::from_private_key(*owner.sessionless.private_key()))
+        .signed_public_bdos(true)
+        .build()
+        .expect("owner");
+    let reader = BDO::new(Some(url), None).with_signed_public_bdos(true);
+
+    let saved = owner.update_bdo("abc", "my_app", &data, &true).await.expect("update");
+    assert_eq!(saved.bdo, data);
+    let (_, payload) = requests.recv().expect("update");
+    assert_eq!(payload["bdo"][ENVELOPE_KEY]["pubKey"], json!(owner_pub_key));
+
+    let public = reader.get_public_bdo("reader", "my_app", &owner_pub_key).await.expect("public");
+    assert_eq!(public.bdo, data);
+
+    let impostor = Sessionless::new().public_key().to_hex();
+    assert!(matches!(reader.get_public_bdo("reader", "my_app", &impostor).await, Err(BdoError::Unverified(_))));
+    assert!(matches!(reader.get_bdo_by_emojicode("🌟").await, Err(BdoError::Unverified(_))));
+    assert!(matches!(reader.get_public_bdo("reader", "my_app", &owner_pub_key).await, Err(BdoError::Unverified(_))));
+}
+
+#[actix_rt::test]
+async fn test_canonical_json_and_unchanged_writes() {
+    use crate::canonical::{content_hash, to_canonical_string};
+
+    let numbers: Value = serde_json::from_str("[1.0, -0.0, 1e21, 0.000001, 1e-7, 1.5, 9007199254740992, -9007199254740993]").expect("json");
+    assert_eq!(to_canonical_string(&numbers), "[1,0,1e+21,0.000001,1e-7,1.5,9007199254740992,-9007199254740992]");
+    assert_eq!(to_canonical_string(&json!("é\n\"/")), r#""é\n\"/""#);
+
+    // Integers and integral floats past 2^53 keep only the digits JavaScript would print.
+    let floats: Value = serde_json::from_str("[9007199254740992.0, 1152921504606846976.0, -1152921504606846976.0, 1.7976931348623157e308, 5e-324, 123.456, -1.5e-7]").expect("json");
+    assert_eq!(to_canonical_string(&floats), "[9007199254740992,1152921504606847000,-1152921504606847000,1.7976931348623157e+308,5e-324,123.456,-1.5e-7]");
+    let integers: Value = serde_json::from_str("[1152921504606846976, -1152921504606846976, 12345678901234567890]").expect("json");
+    assert_eq!(to_canonical_string(&integers), "[1152921504606847000,-1152921504606847000,12345678901234567000]");
+    assert_eq!(content_hash(&json!(1152921504606846976u64)), content_hash(&json!(1152921504606846976.0)));
+
+    let a: Value = serde_json::from_str(r#"{"price": 10.0, "name": "Book", "tags": ["a", "b"]}"#).expect("a");
+    let b: Value = serde_json::from_str(r#"{"tags": ["a", "b"], "name": "Book", "price": 10}"#).expect("b");
+    assert_eq!(content_hash(&a), content_hash(&b));
+    assert_ne!(content_hash(&a), content_hash(&json!({ "tags": ["b", "a"], "name": "Book", "price": 10 })));
+
+    let (url, requests) = stand_in_server_with(vec![
+        (200, r#"{"uuid":"abc","bdo":{"name":"Book"}}"#),
+        (200, r#"{"uuid":"abc","bdo":{"name":"Book 2"}}"#),
+    ]);
+    let bdo = BDO::new(Some(url), None).with_skip_unchanged_writes(true);
+
+    bdo.update_bdo("abc", "my_app", &a, &false).await.expect("first write");
+    let skipped = bdo.update_bdo("abc", "my_app", &b, &false).await.expect("skipped write");
+    assert_eq!(skipped.bdo, b);
+    bdo.update_bdo("abc", "my_app", &json!({ "name": "Book 2" }), &false).await.expect("changed write");
+
+    let (_, first) = requests.recv().expect("first");
+    assert_eq!(first["bdo"]["name"], "Book");
+    let (_, changed) = requests.recv().expect("changed");
+    assert_eq!(changed["bdo"]["name"], "Book 2");
+}
+
+#[actix_rt::test]
+async fn test_fake_server_checks_requests() {
+    use crate::fake::FakeBdoServer;
+    use crate::structs::TeleportResponse;
+    use crate::BdoError;
+
+    let server = FakeBdoServer::start().expect("fake server");
+    let bdo = BDO::new(Some(server.url().to_string()), None);
+    let stranger = BDO::new(Some(server.url().to_string()), None);
+    let pub_key = bdo.sessionless.public_key().to_hex();
+
+    let user = bdo.create_user("hash", &json!({ "foo": "bar" }), &true).await.expect("create");
+    assert_eq!(server.bdo(&user.uuid, "hash"), Some(json!({ "foo": "bar" })));
+    assert_eq!(server.public_bdo(&pub_key), Some(json!({ "foo": "bar" })));
+
+    // Someone else's key can't read or write this user's BDOs.
+    assert!(matches!(stranger.get_bdo(&user.uuid, "hash").await, Err(BdoError::Auth { .. })));
+    assert!(matches!(stranger.update_bdo(&user.uuid, "hash", &json!({}), &false).await, Err(BdoError::Auth { .. })));
+
+    // The server answers a public write over a BDO locked to another pubKey
+    // with an auth error, which the client reads back to tell apart.
+    let locked = json!({ "pub": true, "pubKey": "someone else" });
+    bdo.update_bdo(&user.uuid, "hash", &locked, &false).await.expect("private write");
+    assert!(matches!(bdo.update_bdo(&user.uuid, "hash", &json!({ "foo": "bar" }), &true).await, Err(BdoError::PubKeyConflict { .. })));
+    assert_eq!(server.bdo(&user.uuid, "hash"), Some(locked));
+    bdo.update_bdo(&user.uuid, "hash", &json!({ "foo": "bar" }), &false).await.expect("private write");
+    bdo.update_bdo(&user.uuid, "hash", &json!({ "foo": "bar" }), &true).await.expect("public write");
+
+    let stale = reqwest::get(format!("{}user/{}/bdo?timestamp=1&hash=hash&signature=00", server.url(), user.uuid)).await.expect("stale request");
+    assert_eq!(stale.text().await.expect("body"), r#"{"error":"no time like the present"}"#);
+
+    let emojicode = bdo.get_emojicode_for_pub_key(&pub_key).await.expect("emojicode").emojicode;
+    assert!(emojicode.starts_with(crate::fake::EMOJICODE_PREFIX));
+    let short_code = server.assign_short_code(&pub_key);
+    assert_eq!(short_code, "000000001");
+    assert_eq!(bdo.get_bdo_by_short_code(&short_code).await.expect("short code").bdo["foo"], "bar");
+    assert!(matches!(bdo.get_bdo_by_short_code("000000002").await, Err(BdoError::NotFound { .. })));
+
+    bdo.add_template("my_app", &emojicode).await.expect("add template");
+    let templates = bdo.get_templates("my_app").await.expect("templates");
+    assert_eq!(templates[0].pub_key, pub_key);
+    assert_eq!(templates[0].bdo["foo"], "bar");
+
+    let url = "https://example.com/product?pubKey=02abc";
+    server.add_teleport(url, &TeleportResponse { html: Some("<teleport></teleport>".to_string()), ..TeleportResponse::default() });
+    assert!(bdo.teleport(&user.uuid, "hash", url).await.expect("teleport").valid);
+    assert!(!bdo.teleport(&user.uuid, "hash", "https://example.com/elsewhere").await.expect("teleport").valid);
+
+    bdo.delete_user(&user.uuid, "hash").await.expect("delete");
+    assert_eq!(server.bdo(&user.uuid, "hash"), None);
+    assert!(matches!(bdo.get_bdo(&user.uuid, "hash").await, Err(BdoError::Auth { .. })));
+}
+
+#[actix_rt::test]
+async fn test_bdo_api_is_mockable() {
+    use crate::mock::{MockBdo, MockCall};
+    use crate::{BdoApi, BdoError};
+    use std::sync::Arc;
+
+    /// Code under test only knows it has some BDO client.
+    async fn rename(api: &dyn BdoApi, uuid: &str, name: &str) -> Result<Value, BdoError> {
+        let mut bdo = api.get_bdo(uuid, "hash").await?.bdo;
+        bdo["name"] = json!(name);
+        api.update_bdo(uuid, "hash", &bdo, &false).await.map(|user| user.bdo)
+    }
+
+    let mock = MockBdo::new();
+    mock.insert_bdo("uuid", "hash", json!({ "name": "old", "kept": true }));
+    assert_eq!(rename(&mock, "uuid", "new").await.expect("rename"), json!({ "name": "new", "kept": true }));
+    assert_eq!(mock.bdo("uuid", "hash"), Some(json!({ "name": "new", "kept": true })));
+    assert_eq!(mock.calls_to("get_bdo"), vec![MockCall { method: "get_bdo", args: json!({ "uuid": "uuid", "hash": "hash" }) }]);
+    assert_eq!(mock.calls().iter().map(|call| call.method).collect::<Vec<_>>(), vec!["get_bdo", "update_bdo"]);
+
+    mock.clear_calls();
+    mock.fail_next("update_bdo", BdoError::Config("scripted".to_string()));
+    assert!(matches!(rename(&mock, "uuid", "newer").await, Err(BdoError::Config(_))));
+    assert_eq!(mock.calls().len(), 2);
+    assert_eq!(mock.bdo("uuid", "hash").expect("bdo")["name"], "new");
+
+    let api: Arc<dyn BdoApi> = Arc::new(MockBdo::new().with_pub_key("02abc"));
+    let user = api.create_user("hash", &json!({ "foo": "bar" }), &true).await.expect("create");
+    assert_eq!(user.uuid.len(), 36);
+    let emojicode = api.get_emojicode_for_pub_key("02abc").await.expect("emojicode").emojicode;
+    assert_eq!(api.get_bdo_by_emojicode(&emojicode).await.expect("emojicode").bdo["foo"], "bar");
+    assert!(matches!(api.get_bdo_by_emojicode("nope").await, Err(BdoError::NotFound { .. })));
+
+    // The real client goes through the same trait.
+    let server = crate::fake::FakeBdoServer::start().expect("fake server");
+    let bdo = BDO::new(Some(server.url().to_string()), None);
+    let user = bdo.create_user("hash", &json!({ "name": "old" }), &false).await.expect("create");
+    assert_eq!(rename(&bdo, &user.uuid, "new").await.expect("rename")["name"], "new");
+    assert_eq!(server.bdo(&user.uuid, "hash"), Some(json!({ "name": "new" })));
+}
+
+#[actix_rt::test]
+async fn test_compare_and_swap_updates() {
+    use crate::{BdoError, BlockingBDO};
+    use std::cell::Cell;
+
+    let server = crate::fake::FakeBdoServer::start().expect("fake server");
+    let device = BDO::new(Some(server.url().to_string()), None);
+    let private_key = *device.sessionless.private_key();
+    let other_device = BDO::new(Some(server.url().to_string()), Some(Sessionless::from_private_key(private_key)));
+    let user = device.create_user("hash", &json!({ "count": 0 }), &false).await.expect("create");
+
+    // Another device writes between our read and our swap.
+    let (base, version) = device.get_bdo_versioned(&user.uuid, "hash").await.expect("read");
+    assert_eq!(version, crate::canonical::content_hash(&json!({ "count": 0 })));
+    other_device.update_bdo(&user.uuid, "hash", &json!({ "count": 10 }), &false).await.expect("other write");
+    match device.compare_and_swap(&user.uuid, "hash", &base, &json!({ "count": 1 }), &false).await {
+        Err(BdoError::Conflict(conflict)) => {
+            assert_eq!(conflict.base, json!({ "count": 0 }));
+            assert_eq!(conflict.ours, json!({ "count": 1 }));
+            assert_eq!(conflict.theirs, json!({ "count": 10 }));
+        },
+        other => panic!("expected a conflict, got {:?}", other),
+    }
+    assert_eq!(server.bdo(&user.uuid, "hash"), Some(json!({ "count": 10 })));
+
+    let url = server.url().to_string();
+    let uuid = user.uuid.clone();
+    let write_elsewhere = move |bdo: Value| {
+        let (url, uuid) = (url.clone(), uuid.clone());
+        std::thread::spawn(move || {
+            let other_device = BlockingBDO::new(Some(url), Some(Sessionless::from_private_key(private_key)));
+            other_device.update_bdo(&uuid, "hash", &bdo, &false).expect("other write");
+        }).join().expect("other device");
+    };
+
+    // The first try loses the race, so the update runs again on the other device's BDO.
+    let runs = Cell::new(0);
+    let updated = device.update_bdo_with(&user.uuid, "hash", &false, |bdo| {
+        runs.set(runs.get() + 1);
+        if runs.get() == 1 {
+            write_elsewhere(json!({ "count": 100 }));
+        }
+        Ok(json!({ "count": bdo["count"].as_i64().unwrap_or_default() + 1 }))
+    }).await.expect("update");
+    assert_eq!(runs.get(), 2);
+    assert_eq!(updated.bdo, json!({ "count": 101 }));
+    assert_eq!(server.bdo(&user.uuid, "hash"), Some(json!({ "count": 101 })));
+
+    let merged = device.update_bdo_with_merge(&user.uuid, "hash", &false, |bdo| {
+        write_elsewhere(json!({ "count": 101, "theirs": true }));
+        let mut ours = bdo.clone();
+        ours["ours"] = json!(true);
+        Ok(ours)
+    }, |conflict| {
+        let mut merged = conflict.theirs.clone();
+        merged["ours"] = conflict.ours["ours"].clone();
+        Ok(merged)
+    }).await.expect("merge");
+    assert_eq!(merged.bdo, json!({ "count": 101, "theirs": true, "ours": true }));
+
+    // A device that always loses gives up after its attempts.
+    let device = device.with_cas_attempts(2);
+    let result = device.update_bdo_with(&user.uuid, "hash", &false, |bdo| {
+        write_elsewhere(json!({ "count": rand::random::<u32>() }));
+        Ok(bdo.clone())
+    }).await;
+    assert!(matches!(result, Err(BdoError::Conflict(_))));
+}
+
+#[test]
+fn test_json_patch_and_merge_patch() {
+    use crate::patch::{apply_merge_patch, apply_patch, diff, merge_diff, PatchOperation};
+    use crate::BdoError;
+
+    let doc = json!({ "foo": ["bar", "baz"], "a/b": 1, "m~n": { "deep": true } });
+    let patch: Vec<PatchOperation> = serde_json::from_value(json!([
+        { "op": "test", "path": "/a~1b", "value": 1.0 },
+        { "op": "add", "path": "/foo/1", "value": "qux" },
+        { "op": "add", "path": "/foo/-", "value": "end" },
+        { "op": "remove", "path": "/foo/0" },
+        { "op": "replace", "path": "/m~0n/deep", "value": false },
+        { "op": "copy", "from": "/m~0n", "path": "/copied" },
+        { "op": "move", "from": "/a~1b", "path": "/moved" }
+    ])).expect("patch");
+    assert_eq!(apply_patch(&doc, &patch).expect("apply"), json!({
+        "foo": ["qux", "baz", "end"],
+        "m~n": { "deep": false },
+        "copied": { "deep": false },
+        "moved": 1
+    }));
+
+    // A failing operation leaves nothing half applied.
+    let failing = vec![
+        PatchOperation::Replace { path: "/a~1b".to_string(), value: json!(2) },
+        PatchOperation::Test { path: "/foo/0".to_string(), value: json!("nope") },
+    ];
+    assert!(matches!(apply_patch(&doc, &failing), Err(BdoError::Patch(_))));
+    for bad in [
+        PatchOperation::Remove { path: "/missing".to_string() },
+        PatchOperation::Add { path: "/foo/9".to_string(), value: json!(1) },
+        PatchOperation::Add { path: "/foo/01".to_string(), value: json!(1) },
+        PatchOperation::Move { from: "/m~0n".to_string(), path: "/m~0n/inner".to_string() },
+        PatchOperation::Replace { path: "no-slash".to_string(), value: json!(1) },
+    ] {
+        assert!(apply_patch(&doc, std::slice::from_ref(&bad)).is_err(), "{:?} should fail", bad);
+    }
+
+    // The example from RFC 7386.
+    let target = json!({ "title": "Goodbye!", "author": { "givenName": "John", "familyName": "Doe" }, "tags": ["example", "sample"], "content": "This will be unchanged" });
+    let merge = json!({ "title": "Hello!", "phoneNumber": "+01-123-456-7890", "author": { "familyName": null }, "tags": ["example"] });
+    assert_eq!(apply_merge_patch(&target, &merge), json!({ "title": "Hello!", "author": { "givenName": "John" }, "tags": ["example"], "content": "This will be unchanged", "phoneNumber": "+01-123-456-7890" }));
+
+    let from = json!({ "name": "Old", "tags": ["a", "b", "c"], "nested": { "keep": 1, "drop": 2 }, "n": 1 });
+    let to = json!({ "name": "New", "tags": ["a", "x"], "nested": { "keep": 1, "add": [1] }, "n": 1.0, "extra": null });
+    let patch = diff(&from, &to);
+    // `1` and `1.0` are the same number, so `n` isn't touched.
+    let canonical = crate::canonical::to_canonical_string;
+    assert_eq!(canonical(&apply_patch(&from, &patch).expect("diff applies")), canonical(&to));
+    assert!(!patch.iter().any(|operation| matches!(operation, PatchOperation::Replace { path, .. } if path == "/n")));
+    assert!(diff(&to, &to).is_empty());
+    assert_eq!(diff(&json!([1]), &json!({ "a": 1 })), vec![PatchOperation::Replace { path: String::new(), value: json!({ "a": 1 }) }]);
+
+    let to = json!({ "name": "New", "tags": ["a", "x"], "nested": { "keep": 1, "add": [1] } });
+    let merge = merge_diff(&from, &to);
+    assert_eq!(merge, json!({ "name": "New", "tags": ["a", "x"], "nested": { "drop": null, "add": [1] }, "n": null }));
+    assert_eq!(apply_merge_patch(&from, &merge), to);
+}
+
+#[actix_rt::test]
+async fn test_patch_bdo() {
+    use crate::patch::PatchOperation;
+    use crate::BdoError;
+
+    let server = crate::fake::FakeBdoServer::start().expect("fake server");
+    let bdo = BDO::new(Some(server.url().to_string()), None);
+    let user = bdo.create_user("hash", &json!({ "name": "Old", "items": [1, 2] }), &false).await.expect("create");
+
+    let patched = bdo.patch_bdo(&user.uuid, "hash", &[
+        PatchOperation::Test { path: "/name".to_string(), value: json!("Old") },
+        PatchOperation::Replace { path: "/name".to_string(), value: json!("New") },
+        PatchOperation::Add { path: "/items/-".to_string(), value: json!(3) },
+    ], &false).await.expect("patch");
+    assert_eq!(patched.bdo, json!({ "name": "New", "items": [1, 2, 3] }));
+
+    let failed = bdo.patch_bdo(&user.uuid, "hash", &[
+        PatchOperation::Remove { path: "/items".to_string() },
+        PatchOperation::Test { path: "/name".to_string(), value: json!("Old") },
+    ], &false).await;
+    assert!(matches!(failed, Err(BdoError::Patch(_))));
+    assert_eq!(server.bdo(&user.uuid, "hash"), Some(json!({ "name": "New", "items": [1, 2, 3] })));
+
+    let merged = bdo.merge_patch_bdo(&user.uuid, "hash", &json!({ "items": null, "done": true }), &false).await.expect("merge patch");
+    assert_eq!(merged.bdo, json!({ "name": "New", "done": true }));
+    assert_eq!(server.bdo(&user.uuid, "hash"), Some(json!({ "name": "New", "done": true })));
+}
+
+#[actix_rt::test]
+async fn test_cached_reads() {
+    use crate::cache::{CachePolicy, FileCacheStore, Freshness, MemoryCacheStore};
+    use crate::retry::RetryPolicy;
+    use crate::BdoError;
+    use std::time::Duration;
+
+    let server = crate::fake::FakeBdoServer::start().expect("fake server");
+    let bdo = BDO::new(Some(server.url().to_string()), None)
+        .with_cache(MemoryCacheStore::new(), CachePolicy::new(Duration::from_secs(60)));
+    let other_device = BDO::new(Some(server.url().to_string()), Some(Sessionless::from_private_key(*bdo.sessionless.private_key())));
+    let user = bdo.create_user("hash", &json!({ "count": 0 }), &false).await.expect("create");
+
+    let first = bdo.get_bdo_cached(&user.uuid, "hash").await.expect("first read");
+    assert_eq!(first.freshness, Freshness::Live);
+    other_device.update_bdo(&user.uuid, "hash", &json!({ "count": 1 }), &false).await.expect("other write");
+    let second = bdo.get_bdo_cached(&user.uuid, "hash").await.expect("second read");
+    assert_eq!(second.freshness, Freshness::Fresh);
+    assert_eq!(second.value.bdo, json!({ "count": 0 }));
+
+    // Our own writes drop what they touch.
+    bdo.update_bdo(&user.uuid, "hash", &json!({ "count": 2 }), &false).await.expect("update");
+    let third = bdo.get_bdo_cached(&user.uuid, "hash").await.expect("third read");
+    assert_eq!(third.freshness, Freshness::Live);
+    assert_eq!(third.value.bdo, json!({ "count": 2 }));
+
+    bdo.set_offline(true);
+    assert!(bdo.is_offline());
+    let offline = bdo.get_bdo_cached(&user.uuid, "hash").await.expect("offline read");
+    assert_eq!(offline.freshness, Freshness::Offline);
+    assert_eq!(offline.value.bdo, json!({ "count": 2 }));
+    assert!(matches!(bdo.get_bases(&user.uuid, "hash").await, Err(BdoError::Offline(_))));
+    bdo.set_offline(false);
+
+    // A stale entry is returned straight away and refreshed behind it.
+    let bdo = BDO::new(Some(server.url().to_string()), Some(Sessionless::from_private_key(*bdo.sessionless.private_key())))
+        .with_cache(MemoryCacheStore::new(), CachePolicy::new(Duration::ZERO).with_stale_while_revalidate(Duration::from_secs(60)));
+    bdo.get_bdo(&user.uuid, "hash").await.expect("read");
+    other_device.update_bdo(&user.uuid, "hash", &json!({ "count": 3 }), &false).await.expect("other write");
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    let stale = bdo.get_bdo_cached(&user.uuid, "hash").await.expect("stale read");
+    assert!(stale.is_stale());
+    assert_eq!(stale.value.bdo, json!({ "count": 2 }));
+    bdo.finish_refreshes().await;
+    bdo.set_offline(true);
+    assert_eq!(bdo.get_bdo(&user.uuid, "hash").await.expect("refreshed read").bdo, json!({ "count": 3 }));
+
+    // A refresh that started before an invalidation, like the one our own
+    // writes make, doesn't put the old BDO back. The refresh can't run until
+    // this test awaits, so it always finishes after the invalidation.
+    bdo.set_offline(false);
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    assert!(bdo.get_bdo_cached(&user.uuid, "hash").await.expect("stale read").is_stale());
+    bdo.invalidate_cache(&user.uuid, "hash");
+    bdo.finish_refreshes().await;
+    bdo.set_offline(true);
+    assert!(matches!(bdo.get_bdo(&user.uuid, "hash").await, Err(BdoError::Offline(_))));
+
+    bdo.set_offline(false);
+    bdo.get_bdo(&user.uuid, "hash").await.expect("read");
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    assert!(bdo.get_bdo_cached(&user.uuid, "hash").await.expect("stale read").is_stale());
+    bdo.update_bdo(&user.uuid, "hash", &json!({ "count": 4 }), &false).await.expect("update");
+    bdo.finish_refreshes().await;
+    bdo.set_offline(true);
+    assert!(matches!(bdo.get_bdo(&user.uuid, "hash").await, Err(BdoError::Offline(_))));
+    bdo.set_offline(false);
+    assert_eq!(bdo.get_bdo(&user.uuid, "hash").await.expect("read").bdo, json!({ "count": 4 }));
+
+    // Once the server is gone, reads fall back to the files left behind.
+    let dir = std::env::temp_dir().join(format!("bdo-rs-cache-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let private_key = *bdo.sessionless.private_key();
+    let bdo = BDO::new(Some(server.url().to_string()), Some(Sessionless::from_private_key(private_key)))
+        .with_retry_policy(RetryPolicy::none())
+        .with_cache(FileCacheStore::new(&dir), CachePolicy::new(Duration::ZERO).with_stale_while_revalidate(Duration::ZERO));
+    assert_eq!(bdo.get_bdo_cached(&user.uuid, "hash").await.expect("read").freshness, Freshness::Live);
+    let url = server.url().to_string();
+    drop(server);
+
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    let fallback = bdo.get_bdo_cached(&user.uuid, "hash").await.expect("fallback read");
+    assert_eq!(fallback.freshness, Freshness::Offline);
+    assert_eq!(fallback.value.bdo, json!({ "count": 4 }));
+
+    let restarted = BDO::new(Some(url.clone()), Some(Sessionless::from_private_key(private_key)))
+        .with_cache(FileCacheStore::new(&dir), CachePolicy::default());
+    restarted.set_offline(true);
+    assert_eq!(restarted.get_bdo(&user.uuid, "hash").await.expect("read from disk").bdo, json!({ "count": 4 }));
+
+    // Other users and other servers sharing the directory don't see the entry.
+    let other_user = BDO::new(Some(url), None).with_cache(FileCacheStore::new(&dir), CachePolicy::default());
+    other_user.set_offline(true);
+    assert!(matches!(other_user.get_bdo(&user.uuid, "hash").await, Err(BdoError::Offline(_))));
+    let other_server = BDO::new(Some("http://127.0.0.1:9/".to_string()), Some(Sessionless::from_private_key(private_key)))
+        .with_cache(FileCacheStore::new(&dir), CachePolicy::default());
+    other_server.set_offline(true);
+    assert!(matches!(other_server.get_bdo(&user.uuid, "hash").await, Err(BdoError::Offline(_))));
+
+    let files: Vec<_> = std::fs::read_dir(&dir).expect("cache dir").map(|entry| entry.expect("entry").path()).collect();
+    assert!(files.iter().all(|path| path.extension().is_some_and(|extension| extension == "bdocache")));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        for path in &files {
+            assert_eq!(std::fs::metadata(path).expect("metadata").permissions().mode() & 0o777, 0o600);
+        }
+    }
+    restarted.clear_cache();
+    assert!(matches!(restarted.get_bdo(&user.uuid, "hash").await, Err(BdoError::Offline(_))));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[actix_rt::test]
+async fn test_compare_and_swap_skips_the_cache() {
+    use crate::cache::{CachePolicy, MemoryCacheStore};
+    use crate::BdoError;
+    use std::time::Duration;
+
+    let server = crate::fake::FakeBdoServer::start().expect("fake server");
+    let device = BDO::new(Some(server.url().to_string()), None)
+        .with_cache(MemoryCacheStore::new(), CachePolicy::new(Duration::from_secs(60)));
+    let other_device = BDO::new(Some(server.url().to_string()), Some(Sessionless::from_private_key(*device.sessionless.private_key())));
+    let user = device.create_user("hash", &json!({ "count": 0 }), &false).await.expect("create");
+
+    let base = device.get_bdo(&user.uuid, "hash").await.expect("read").bdo;
+    other_device.update_bdo(&user.uuid, "hash", &json!({ "count": 10 }), &false).await.expect("other write");
+    assert_eq!(device.get_bdo(&user.uuid, "hash").await.expect("cached read").bdo, base);
+
+    match device.compare_and_swap(&user.uuid, "hash", &base, &json!({ "count": 1 }), &false).await {
+        Err(BdoError::Conflict(conflict)) => assert_eq!(conflict.theirs, json!({ "count": 10 })),
+        other => panic!("expected a conflict, got {:?}", other),
+    }
+    assert_eq!(server.bdo(&user.uuid, "hash"), Some(json!({ "count": 10 })));
+
+    let updated = device.update_bdo_with(&user.uuid, "hash", &false, |bdo| {
+        Ok(json!({ "count": bdo["count"].as_i64().unwrap_or_default() + 1 }))
+    }).await.expect("update");
+    assert_eq!(updated.bdo, json!({ "count": 11 }));
+}
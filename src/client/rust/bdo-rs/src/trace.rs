@@ -0,0 +1,100 @@
+//! Optional `tracing` output for the BDO client.
+//!
+//! Nothing is logged unless the crate's `tracing` feature is enabled and the
+//! application installs a subscriber. Signatures, hashes and private keys are
+//! redacted before anything is recorded.
+
+use serde_json::Value;
+
+pub const REDACTED: &str = "[redacted]";
+
+/// Keys whose values never show up in logs, in payloads or URL query strings.
+const SENSITIVE_KEYS: &[&str] = &["signature", "casterSignature", "hash", "privateKey", "private_key", "passphrase"];
+
+fn is_sensitive(key: &str) -> bool {
+    SENSITIVE_KEYS.iter().any(|sensitive| sensitive.eq_ignore_ascii_case(key))
+}
+
+/// Replaces sensitive query parameters in a URL.
+pub fn redact_url(url: &str) -> String {
+    let Some((path, query)) = url.split_once('?') else {
+        return url.to_string();
+    };
+
+    let params: Vec<String> = query.split('&')
+        .map(|param| match param.split_once('=') {
+            Some((key, _)) if is_sensitive(key) => format!("{}={}", key, REDACTED),
+            _ => param.to_string(),
+        })
+        .collect();
+
+    format!("{}?{}", path, params.join("&"))
+}
+
+/// Replaces sensitive fields anywhere in a JSON payload.
+pub fn redact_value(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(map.iter()
+            .map(|(key, value)| {
+                if is_sensitive(key) {
+                    (key.clone(), Value::String(REDACTED.to_string()))
+                } else {
+                    (key.clone(), redact_value(value))
+                }
+            })
+            .collect()),
+        Value::Array(values) => Value::Array(values.iter().map(redact_value).collect()),
+        _ => value.clone(),
+    }
+}
+
+pub(crate) fn request(method: &str, url: &str, payload: Option<&Value>) {
+    #[cfg(feature = "tracing")]
+    {
+        tracing::debug!(method, url = %redact_url(url), "sending request");
+        if let Some(payload) = payload {
+            tracing::trace!(payload = %redact_value(payload), "request payload");
+        }
+    }
+    #[cfg(not(feature = "tracing"))]
+    let _ = (method, url, payload);
+}
+
+pub(crate) fn response(status: reqwest::StatusCode, body: &str) {
+    #[cfg(feature = "tracing")]
+    {
+        tracing::debug!(status = status.as_u16(), "received response");
+        if tracing::enabled!(tracing::Level::TRACE) {
+            let body = serde_json::from_str::<Value>(body)
+                .map(|value| redact_value(&value).to_string())
+                .unwrap_or_else(|_| format!("<{} bytes>", body.len()));
+            tracing::trace!(%body, "response body");
+        }
+    }
+    #[cfg(not(feature = "tracing"))]
+    let _ = (status, body);
+}
+
+pub(crate) fn failure(err: &crate::BdoError) {
+    #[cfg(feature = "tracing")]
+    match err {
+        crate::BdoError::Transport(_) | crate::BdoError::Decode { .. } => tracing::warn!(error = %err, "BDO request failed"),
+        _ => tracing::debug!(error = %err, "BDO request was rejected"),
+    }
+    #[cfg(not(feature = "tracing"))]
+    let _ = err;
+}
+
+pub(crate) fn retry(attempt: u32, err: &crate::BdoError) {
+    #[cfg(feature = "tracing")]
+    tracing::info!(attempt, error = %err, "retrying BDO request");
+    #[cfg(not(feature = "tracing"))]
+    let _ = (attempt, err);
+}
+
+pub(crate) fn untranslated_url(url: &str, err: &crate::BdoError) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(url = %redact_url(url), error = %err, "using a base URL that couldn't be translated");
+    #[cfg(not(feature = "tracing"))]
+    let _ = (url, err);
+}
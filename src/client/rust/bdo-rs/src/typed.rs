@@ -0,0 +1,90 @@
+//! Reading and writing BDOs as your own Rust types instead of `serde_json::Value`.
+//!
+//! Conversions go through `serde_path_to_error`, so a BDO that doesn't fit
+//! its type fails with `BdoError::Typed` naming the field that didn't.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use crate::structs::{BDOUser, EmojicodeResponse, ShortCodeResponse};
+use crate::{BdoError, BDO};
+
+/// Deserializes a BDO into `T`.
+pub fn from_bdo<T: DeserializeOwned>(bdo: Value) -> Result<T, BdoError> {
+    serde_path_to_error::deserialize(bdo).map_err(|err| BdoError::Typed {
+        path: err.path().to_string(),
+        source: err.into_inner(),
+    })
+}
+
+/// Serializes `T` into the JSON the server stores as a BDO.
+pub fn to_bdo<T: Serialize + ?Sized>(bdo: &T) -> Result<Value, BdoError> {
+    serde_path_to_error::serialize(bdo, serde_json::value::Serializer).map_err(|err| BdoError::Typed {
+        path: err.path().to_string(),
+        source: err.into_inner(),
+    })
+}
+
+impl BDOUser {
+    pub fn into_typed<T: DeserializeOwned>(self) -> Result<BDOUser<T>, BdoError> {
+        Ok(BDOUser {
+            uuid: self.uuid,
+            bdo: from_bdo(self.bdo)?,
+        })
+    }
+}
+
+impl EmojicodeResponse {
+    pub fn into_typed<T: DeserializeOwned>(self) -> Result<EmojicodeResponse<T>, BdoError> {
+        Ok(EmojicodeResponse {
+            emojicode: self.emojicode,
+            pub_key: self.pub_key,
+            bdo: from_bdo(self.bdo)?,
+            created_at: self.created_at,
+        })
+    }
+}
+
+impl ShortCodeResponse {
+    pub fn into_typed<T: DeserializeOwned>(self) -> Result<ShortCodeResponse<T>, BdoError> {
+        Ok(ShortCodeResponse {
+            short_code: self.short_code,
+            pub_key: self.pub_key,
+            bdo: from_bdo(self.bdo)?,
+        })
+    }
+}
+
+impl BDO {
+    /// `create_user` with a typed BDO.
+    pub async fn create_user_typed<T: Serialize + DeserializeOwned>(&self, hash: &str, bdo: &T, is_public: &bool) -> Result<BDOUser<T>, BdoError> {
+        let bdo = to_bdo(bdo)?;
+        self.create_user(hash, &bdo, is_public).await?.into_typed()
+    }
+
+    /// `update_bdo` with a typed BDO. The BDO the server saved is read back as `T`.
+    pub async fn update_bdo_typed<T: Serialize + DeserializeOwned>(&self, uuid: &str, hash: &str, bdo: &T, is_public: &bool) -> Result<BDOUser<T>, BdoError> {
+        let bdo = to_bdo(bdo)?;
+        self.update_bdo(uuid, hash, &bdo, is_public).await?.into_typed()
+    }
+
+    /// `get_bdo`, deserialized into `T`.
+    pub async fn get_bdo_as<T: DeserializeOwned>(&self, uuid: &str, hash: &str) -> Result<BDOUser<T>, BdoError> {
+        self.get_bdo(uuid, hash).await?.into_typed()
+    }
+
+    /// `get_public_bdo`, deserialized into `T`.
+    pub async fn get_public_bdo_as<T: DeserializeOwned>(&self, uuid: &str, hash: &str, pub_key: &str) -> Result<BDOUser<T>, BdoError> {
+        self.get_public_bdo(uuid, hash, pub_key).await?.into_typed()
+    }
+
+    /// `get_bdo_by_emojicode`, deserialized into `T`.
+    pub async fn get_bdo_by_emojicode_as<T: DeserializeOwned>(&self, emojicode: &str) -> Result<EmojicodeResponse<T>, BdoError> {
+        self.get_bdo_by_emojicode(emojicode).await?.into_typed()
+    }
+
+    /// `get_bdo_by_short_code`, deserialized into `T`.
+    pub async fn get_bdo_by_short_code_as<T: DeserializeOwned>(&self, short_code: &str) -> Result<ShortCodeResponse<T>, BdoError> {
+        self.get_bdo_by_short_code(short_code).await?.into_typed()
+    }
+}